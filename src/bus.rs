@@ -0,0 +1,425 @@
+use modVM::*;
+use modVM::Query::*;
+use modVM::Response::*;
+use crate::console::{ConsoleIn, ConsoleOut, Input};
+use crate::interrupt::{Controller, Interrupts, INPUT_IRQ, TIMER_IRQ};
+use crate::ram::Ram;
+use crate::rng::Rng;
+use crate::timer::Timer;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// Where `Bus::standard` places the console mailbox.
+pub const CONSOLE_BASE: u16 = 8080;
+/// Where `Bus::standard` places the input device.
+pub const INPUT_BASE: u16 = 8082;
+/// Where `Bus::standard` places the timer.
+pub const TIMER_BASE: u16 = 8084;
+/// Where `Bus::standard` places the interrupt controller.
+pub const INTERRUPT_BASE: u16 = 8088;
+/// Where `run --disk` places the disk controller.
+pub const DISK_BASE: u16 = 8092;
+/// Where `run --boot-rom` places the boot ROM unless given `--boot-addr`.
+pub const BOOT_BASE: u16 = 0xF800;
+/// Where `Bus::standard` places the random number generator.
+pub const RNG_BASE: u16 = 8096;
+/// Where `run --framebuffer` places the framebuffer.
+pub const FRAMEBUFFER_BASE: u16 = 0xF000;
+/// The first address past the window `Bus::standard` leaves for devices.
+/// Addresses in the window that no device claims fail.
+pub const IO_END: u16 = 8128;
+
+/// What the host, rather than the program, can do with a device: look at and
+/// fill in its words without the side effects a program's loads and saves
+/// have, such as starting a disk command or stepping a generator.
+pub trait Inspect: Peripheral<u16> {
+    /// The word at `offset`, or `None` if it cannot be read without side effects.
+    fn peek(&self, _offset: u16) -> Option<u16> {
+        None
+    }
+
+    /// Stores `value` at `offset` without side effects, returning whether it could.
+    fn poke(&mut self, _offset: u16, _value: u16) -> bool {
+        false
+    }
+
+    /// Whether the device is memory, which programs can be loaded into. Other
+    /// devices are only poked to put back registers a snapshot recorded.
+    fn is_memory(&self) -> bool {
+        false
+    }
+}
+
+pub type Device = Box<dyn Inspect + Send>;
+
+struct Mapping {
+    name: String,
+    base: u16,
+    len: u32,
+    device: Device,
+}
+
+/// Checks that `len` addresses from `base` fit in memory, returning the
+/// address just past them.
+fn bounds(name: &str, base: u16, len: u32) -> Result<u32, String> {
+    let end = base as u32 + len;
+
+    if len == 0 {
+        return Err(format!("`{}` at {} has no addresses", name, base));
+    }
+    if end > 65536 {
+        return Err(format!("`{}` at {}..{} runs past the end of memory", name, base, end - 1));
+    }
+    Ok(end)
+}
+
+impl Mapping {
+    /// The last address mapped.
+    fn end(&self) -> u32 {
+        self.base as u32 + self.len - 1
+    }
+}
+
+/// A transfer a device asks the bus to make on its behalf, through the
+/// sender from `Bus::dma`.
+pub enum Dma {
+    /// Saves `words` from `address` onwards.
+    Save { address: u16, words: Vec<u16> },
+    /// Loads `len` words from `address` onwards and sends them back on
+    /// `reply`. Words that fail to load are read as 0.
+    Load { address: u16, len: u16, reply: Sender<Vec<u16>> },
+}
+
+/// Routes each query to the device that owns its address, translated so that
+/// every device sees its own range starting from 0. Addresses no device owns
+/// fail.
+///
+/// Transfers requested through `Bus::dma` are made as soon as the query that
+/// caused them has been handled, before the next query arrives.
+pub struct Bus {
+    mappings: Vec<Mapping>,
+    dma: Sender<Dma>,
+    transfers: Receiver<Dma>,
+}
+
+impl Bus {
+    pub fn new() -> Bus {
+        let (dma, transfers) = channel();
+        Bus {
+            mappings: vec![],
+            dma,
+            transfers,
+        }
+    }
+
+    /// Where devices send the transfers they want made.
+    pub fn dma(&self) -> Sender<Dma> {
+        self.dma.clone()
+    }
+
+    /// The machine's usual layout: RAM everywhere except the device window
+    /// from `CONSOLE_BASE` up to `IO_END`, which holds the console, the input
+    /// device, reading from `input`, at `INPUT_BASE`, the timer at
+    /// `TIMER_BASE`, the controller for `interrupts` at `INTERRUPT_BASE` and
+    /// the random number generator, starting from `seed`, at `RNG_BASE`.
+    pub fn standard(input: Input, interrupts: &Interrupts, seed: u16) -> Bus {
+        let console = CONSOLE_BASE as u32;
+        let ram = IO_END as u32;
+
+        let mut keyboard = ConsoleIn::new(input);
+        keyboard.connect(interrupts.line(INPUT_IRQ));
+        let mut timer = Timer::new();
+        timer.connect(interrupts.line(TIMER_IRQ));
+
+        let mut bus = Bus::new();
+        let _ = bus.attach("ram", 0, console, Box::new(Ram::new(console as usize)));
+        let _ = bus.attach("console", CONSOLE_BASE, 2, Box::new(ConsoleOut::new()));
+        let _ = bus.attach("input", INPUT_BASE, 2, Box::new(keyboard));
+        let _ = bus.attach("timer", TIMER_BASE, 4, Box::new(timer));
+        let _ = bus.attach("interrupts", INTERRUPT_BASE, 3, Box::new(Controller(interrupts.clone())));
+        let _ = bus.attach("rng", RNG_BASE, 2, Box::new(Rng::new(seed)));
+        let _ = bus.attach("ram", ram as u16, 65536 - ram, Box::new(Ram::new((65536 - ram) as usize)));
+        bus
+    }
+
+    /// Gives `device` the `len` addresses starting at `base`, failing if any of
+    /// them already belong to another device or lie past the end of memory.
+    pub fn attach(&mut self, name: &str, base: u16, len: u32, device: Device) -> Result<(), String> {
+        let end = bounds(name, base, len)?;
+
+        if let Some(x) = self.mappings.iter().find(|d| (d.base as u32) < end && base as u32 <= d.end()) {
+            return Err(format!(
+                "`{}` at {}..{} overlaps `{}` at {}..{}",
+                name, base, end - 1, x.name, x.base, x.end(),
+            ));
+        }
+
+        self.mappings.push(Mapping {
+            name: name.to_string(),
+            base,
+            len,
+            device,
+        });
+        Ok(())
+    }
+
+    /// Gives `device` the `len` addresses starting at `base` in front of
+    /// whatever already owns them, which it shadows until the bus is dropped.
+    pub fn overlay(&mut self, name: &str, base: u16, len: u32, device: Device) -> Result<(), String> {
+        bounds(name, base, len)?;
+
+        self.mappings.insert(0, Mapping {
+            name: name.to_string(),
+            base,
+            len,
+            device,
+        });
+        Ok(())
+    }
+
+    /// Finds the mapping owning `address`, along with the address as its device sees it.
+    fn position(&self, address: u16) -> Option<(usize, u16)> {
+        self.mappings.iter()
+            .position(|d| d.base <= address && ((address - d.base) as u32) < d.len)
+            .map(|d| (d, address - self.mappings[d].base))
+    }
+
+    /// Finds the device owning `address`, along with the address as that device sees it.
+    fn route(&mut self, address: u16) -> Option<(&mut Device, u16)> {
+        let (index, offset) = self.position(address)?;
+        Some((&mut self.mappings[index].device, offset))
+    }
+
+    /// Pokes `words` into memory from `address` onwards, wrapping around at the
+    /// end, and returns the ranges of addresses, inclusive, whose words were
+    /// not stored because no memory owns them. Zeros, such as the padding
+    /// `.org` leaves across the device window, are left out without a mention.
+    pub fn load(&mut self, address: u16, words: &[u16]) -> Vec<(u16, u16)> {
+        let mut skipped: Vec<(u16, u16)> = vec![];
+
+        for (index, word) in words.iter().enumerate() {
+            let x = address.wrapping_add(index as u16);
+            let stored = match self.route(x) {
+                Some((device, offset)) if device.is_memory() => device.poke(offset, *word),
+                _ => false,
+            };
+            if stored || *word == 0 {
+                continue;
+            }
+
+            match skipped.last_mut() {
+                Some((_, end)) if end.wrapping_add(1) == x => *end = x,
+                _ => skipped.push((x, x)),
+            }
+        }
+        skipped
+    }
+
+    /// Pokes `words` in from `address` onwards like `load`, but into device
+    /// registers as well as memory, as resuming a snapshot does. Words that
+    /// cannot be poked are left out.
+    pub fn restore(&mut self, address: u16, words: &[u16]) {
+        for (index, word) in words.iter().enumerate() {
+            self.poke(address.wrapping_add(index as u16), *word);
+        }
+    }
+
+    /// Makes every transfer devices have asked for so far.
+    fn transfer(&mut self) {
+        while let Ok(x) = self.transfers.try_recv() {
+            match x {
+                Dma::Save { address, words } => {
+                    for (offset, word) in words.into_iter().enumerate() {
+                        let _ = self.handle(SaveRequest(word, address.wrapping_add(offset as u16)));
+                    }
+                },
+                Dma::Load { address, len, reply } => {
+                    let words = (0..len)
+                        .map(|d| match self.handle(LoadRequest(address.wrapping_add(d))) {
+                            Ok(Data(x)) => x,
+                            _ => 0,
+                        })
+                        .collect();
+                    let _ = reply.send(words);
+                },
+            }
+        }
+    }
+}
+
+impl Default for Bus {
+    fn default() -> Bus {
+        Bus::new()
+    }
+}
+
+impl Inspect for Bus {
+    fn peek(&self, address: u16) -> Option<u16> {
+        let (index, offset) = self.position(address)?;
+        self.mappings[index].device.peek(offset)
+    }
+
+    fn poke(&mut self, address: u16, value: u16) -> bool {
+        match self.route(address) {
+            Some((device, offset)) => device.poke(offset, value),
+            None => false,
+        }
+    }
+}
+
+impl Peripheral<u16> for Bus {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            model: String::from("Address Decoding Bus v.0.0.0"),
+        }
+    }
+
+    fn handle(&mut self, incoming: Query<u16>) -> Result<Response<u16>, u16> {
+        let response = match incoming {
+            LoadRequest(x) => match self.route(x) {
+                Some((device, offset)) => device.handle(LoadRequest(offset)),
+                None => Ok(Fail(0)),
+            },
+            SaveRequest(x, y) => match self.route(y) {
+                Some((device, offset)) => device.handle(SaveRequest(x, offset)),
+                None => Ok(Fail(0)),
+            },
+        };
+
+        self.transfer();
+        response
+    }
+
+    fn cycle(&mut self) -> Result<(), u16> {
+        for mapping in self.mappings.iter_mut() {
+            mapping.device.cycle()?;
+        }
+        Ok(())
+    }
+
+    fn boot(&mut self) -> Result<(), u16> {
+        for mapping in self.mappings.iter_mut() {
+            mapping.device.boot()?;
+        }
+        Ok(())
+    }
+
+    fn halt(&mut self) -> Result<(), u16> {
+        for mapping in self.mappings.iter_mut() {
+            mapping.device.halt()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::DEFAULT_SEED;
+
+    fn read(bus: &mut Bus, address: u16) -> Option<u16> {
+        match bus.handle(LoadRequest(address)) {
+            Ok(Data(x)) => Some(x),
+            _ => None,
+        }
+    }
+
+    fn write(bus: &mut Bus, address: u16, value: u16) -> bool {
+        matches!(bus.handle(SaveRequest(value, address)), Ok(Good))
+    }
+
+    fn standard() -> Bus {
+        Bus::standard(Input::Bytes(vec![]), &Interrupts::new(), DEFAULT_SEED)
+    }
+
+    fn split() -> Bus {
+        let mut bus = Bus::new();
+        bus.attach("low", 0, 16, Box::new(Ram::new(16))).unwrap();
+        bus.attach("high", 100, 4, Box::new(Ram::new(4))).unwrap();
+        bus
+    }
+
+    #[test]
+    fn routes_by_address() {
+        let mut bus = split();
+        assert!(write(&mut bus, 101, 7));
+        assert!(write(&mut bus, 15, 9));
+        assert_eq!(read(&mut bus, 101), Some(7));
+        assert_eq!(read(&mut bus, 15), Some(9));
+        assert_eq!(read(&mut bus, 1), Some(0));
+
+        // each device sees its own range from 0
+        assert_eq!(bus.peek(101), Some(7));
+        assert_eq!(bus.mappings[1].device.peek(1), Some(7));
+    }
+
+    #[test]
+    fn unowned_addresses_fail() {
+        let mut bus = split();
+        assert_eq!(read(&mut bus, 16), None);
+        assert_eq!(read(&mut bus, 104), None);
+        assert!(!write(&mut bus, 99, 1));
+        assert_eq!(bus.peek(50), None);
+        assert!(!bus.poke(50, 1));
+    }
+
+    #[test]
+    fn loads_skip_what_is_not_memory() {
+        let mut bus = split();
+        assert_eq!(bus.load(14, &[1, 2, 3, 4]), [(16, 17)]);
+        assert_eq!((bus.peek(14), bus.peek(15)), (Some(1), Some(2)));
+
+        let mut bus = standard();
+        assert_eq!(bus.load(CONSOLE_BASE - 2, &[1, 2, 3, 4]), [(CONSOLE_BASE, CONSOLE_BASE + 1)]);
+        assert_eq!(bus.peek(CONSOLE_BASE - 1), Some(2));
+        assert_eq!(read(&mut bus, CONSOLE_BASE), Some(0));
+
+        // padding is not worth mentioning
+        let skipped = bus.load(CONSOLE_BASE - 1, &[0, 0, 5, 0, 6]);
+        assert_eq!(skipped, [(CONSOLE_BASE + 1, CONSOLE_BASE + 1), (INPUT_BASE + 1, INPUT_BASE + 1)]);
+        assert!(bus.load(CONSOLE_BASE, &vec![0; (IO_END - CONSOLE_BASE) as usize]).is_empty());
+    }
+
+    #[test]
+    fn loads_wrap_around() {
+        let mut bus = standard();
+        assert!(bus.load(0xFFFF, &[5, 6]).is_empty());
+        assert_eq!((bus.peek(0xFFFF), bus.peek(0)), (Some(5), Some(6)));
+    }
+
+    #[test]
+    fn restores_reach_device_registers() {
+        let mut bus = standard();
+        bus.restore(CONSOLE_BASE - 1, &[1, 2, 3]);
+        assert_eq!(bus.peek(CONSOLE_BASE - 1), Some(1));
+        assert_eq!((bus.peek(CONSOLE_BASE), bus.peek(CONSOLE_BASE + 1)), (Some(2), Some(3)));
+
+        let interrupts = Interrupts::new();
+        let mut bus = Bus::standard(Input::Bytes(vec![]), &interrupts, DEFAULT_SEED);
+        bus.restore(TIMER_BASE, &[5, 0, 100, 0]);
+        bus.restore(INTERRUPT_BASE, &[0, 0xFFFE, 0x1234]);
+        assert_eq!(bus.peek(TIMER_BASE + 2), Some(100));
+        assert_eq!((interrupts.vector(), bus.peek(INTERRUPT_BASE + 1)), (0x1234, Some(0xFFFE)));
+
+        // but programs never load into them
+        assert_eq!(bus.load(INTERRUPT_BASE, &[1, 2, 3]), [(INTERRUPT_BASE, INTERRUPT_BASE + 2)]);
+        assert_eq!(interrupts.vector(), 0x1234);
+    }
+
+    #[test]
+    fn overlaps_are_refused() {
+        let mut bus = split();
+        let error = bus.attach("clash", 10, 8, Box::new(Ram::new(8))).unwrap_err();
+        assert_eq!(error, "`clash` at 10..17 overlaps `low` at 0..15");
+        assert!(bus.attach("clash", 103, 1, Box::new(Ram::new(1))).is_err());
+        assert!(bus.attach("clash", 99, 1, Box::new(Ram::new(1))).is_ok());
+        assert!(bus.attach("clash", 16, 83, Box::new(Ram::new(83))).is_ok());
+    }
+
+    #[test]
+    fn ranges_must_fit() {
+        let mut bus = Bus::new();
+        assert!(bus.attach("empty", 5, 0, Box::new(Ram::new(0))).is_err());
+        assert!(bus.attach("long", 0xFFFF, 2, Box::new(Ram::new(2))).is_err());
+        assert!(bus.attach("last", 0xFFFF, 1, Box::new(Ram::new(1))).is_ok());
+    }
+}
@@ -1,174 +1,479 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::{read_to_string, write};
 use std::path::Path;
+use crate::expr::evaluate;
+use RISC_16_bit::image::Image;
+use RISC_16_bit::isa::{get_reg, Opcode, OperandKind};
 
-pub fn compile_raw(s: String) -> Result<Vec<u16>, String> {
-    let lines = s.lines()
-        .filter(|d| {
-            d != &""
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    InvalidLabel,
+    DuplicateSymbol,
+    UndefinedSymbol,
+    UnknownInstruction,
+    UnknownRegister,
+    OperandCount,
+    InvalidNumber,
+    InvalidString,
+    UnknownDirective,
+    InvalidOrigin,
+    InvalidExpression,
+    OutOfRange,
+    DuplicateEntry,
+}
+
+impl ErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::InvalidLabel => "A001",
+            ErrorKind::DuplicateSymbol => "A002",
+            ErrorKind::UndefinedSymbol => "A003",
+            ErrorKind::UnknownInstruction => "A004",
+            ErrorKind::UnknownRegister => "A005",
+            ErrorKind::OperandCount => "A006",
+            ErrorKind::InvalidNumber => "A007",
+            ErrorKind::InvalidString => "A008",
+            ErrorKind::UnknownDirective => "A009",
+            ErrorKind::InvalidOrigin => "A010",
+            ErrorKind::InvalidExpression => "A011",
+            ErrorKind::OutOfRange => "A012",
+            ErrorKind::DuplicateEntry => "A013",
+        }
+    }
+}
+
+/// An assembler diagnostic. `line` and `column` are 1-based, and `span` is the
+/// number of characters the diagnostic covers.
+#[derive(Debug, Clone)]
+pub struct AsmError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub span: usize,
+}
+
+impl AsmError {
+    /// Renders the error in the style of rustc, with the offending source line
+    /// and a caret underline.
+    pub fn render(&self, source: &str) -> String {
+        let text = source.lines().nth(self.line - 1).unwrap_or("");
+        let gutter = " ".repeat(self.line.to_string().len());
+        let indent: String = text.chars()
+            .take(self.column - 1)
+            .map(|d| if d == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        format!("error[{}]: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}\n",
+            self.kind.code(), self.message,
+            gutter, self.file, self.line, self.column,
+            gutter,
+            self.line, text,
+            gutter, indent, "^".repeat(self.span.max(1)),
+        )
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: error[{}]: {}", self.file, self.line, self.column, self.kind.code(), self.message)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+impl<'a> Token<'a> {
+    fn len(&self) -> usize {
+        self.text.chars().count()
+    }
+}
+
+struct Statement<'a> {
+    line: usize,
+    item: Item<'a>,
+}
+
+enum Item<'a> {
+    Instruction(Vec<Token<'a>>),
+    Words(Vec<Token<'a>>),
+    Data(Vec<u16>),
+    Origin(u16),
+    Entry(Token<'a>),
+    Init(Token<'a>, Token<'a>),
+}
+
+struct Assembler<'a> {
+    file: &'a str,
+    errors: Vec<AsmError>,
+}
+
+impl<'a> Assembler<'a> {
+    fn error(&mut self, kind: ErrorKind, message: String, line: usize, column: usize, span: usize) {
+        self.errors.push(AsmError {
+            kind,
+            message,
+            file: self.file.to_string(),
+            line,
+            column,
+            span,
         });
-    let mut prg_out = vec![];
+    }
+
+    fn error_at(&mut self, kind: ErrorKind, message: String, line: usize, token: &Token) {
+        self.error(kind, message, line, token.column, token.len());
+    }
+
+    /// Checks that `tokens` (a mnemonic or directive followed by its operands)
+    /// has `expected` operands, reporting an error against the surplus
+    /// operands or the end of the line if not.
+    fn operand_count(&mut self, line: usize, tokens: &[Token], expected: usize) -> bool {
+        let found = tokens.len() - 1;
+
+        if found == expected {
+            return true;
+        }
+
+        let message = format!("`{}` expects {} operand(s), found {}", tokens[0].text, expected, found);
+
+        if found > expected {
+            let first = &tokens[expected + 1];
+            let last = &tokens[found];
+            self.error(ErrorKind::OperandCount, message, line, first.column, last.column + last.len() - first.column);
+        } else {
+            let last = &tokens[found];
+            self.error(ErrorKind::OperandCount, message, line, last.column + last.len(), 1);
+        }
+        false
+    }
+
+    /// Evaluates a constant expression operand to a word. Negative values are
+    /// stored in two's complement.
+    fn value(&mut self, line: usize, token: &Token, symbols: &HashMap<&str, u16>) -> Option<u16> {
+        match evaluate(token.text, symbols) {
+            Ok(x) if (-0x8000..=0xFFFF).contains(&x) => Some(x as u16),
+            Ok(x) => {
+                self.error_at(ErrorKind::OutOfRange, format!("value {} does not fit in a word", x), line, token);
+                None
+            },
+            Err(x) => {
+                self.error(x.kind, x.message, line, token.column + x.offset, x.len);
+                None
+            },
+        }
+    }
+
+    /// Decodes a double-quoted string literal into one word per character.
+    fn string(&mut self, line: usize, token: &Token) -> Option<Vec<u16>> {
+        let text = token.text;
+
+        if text.len() < 2 || !text.starts_with('"') || !text.ends_with('"') {
+            self.error_at(ErrorKind::InvalidString, format!("expected a string literal, found `{}`", text), line, token);
+            return None;
+        }
+
+        match unescape(&text[1..text.len() - 1]) {
+            Ok(x) => Some(x),
+            Err(x) => {
+                self.error_at(ErrorKind::InvalidString, x, line, token);
+                None
+            },
+        }
+    }
+}
+
+/// An assembled program together with the value of every label and constant,
+/// and what its `.entry` and `.init` directives ask for.
+pub struct Assembly {
+    pub words: Vec<u16>,
+    pub symbols: HashMap<String, u16>,
+    pub entry: Option<u16>,
+    pub registers: Vec<(u16, u16)>,
+}
 
-    for (i, line) in lines.enumerate() {
-        let structure: Vec<&str> = line.trim().split(" ").collect();
+impl Assembly {
+    pub fn image(&self) -> Image {
+        Image {
+            words: self.words.clone(),
+            entry: self.entry,
+            registers: self.registers.clone(),
+        }
+    }
+}
 
-        let command = structure[0];
+/// Assembles `s` into the words of an image, with a header if it uses `.entry`
+/// or `.init`.
+pub fn compile_raw(s: &str, file: &str) -> Result<Vec<u16>, Vec<AsmError>> {
+    assemble(s, file).map(|d| d.image().to_words())
+}
+
+pub fn assemble(s: &str, file: &str) -> Result<Assembly, Vec<AsmError>> {
+    let mut asm = Assembler {
+        file,
+        errors: vec![],
+    };
 
-        if command == "HLT" {
-            prg_out.push(0);
+    // first pass: find every label and the address it refers to
+    let mut symbols: HashMap<&str, u16> = HashMap::new();
+    let mut statements = vec![];
+    // kept wider than a word so that running past the end of memory shows
+    let mut address: u32 = 0;
+    let mut entry_line = None;
+
+    for (i, line) in s.lines().enumerate() {
+        let line_no = i + 1;
+        let mut tokens = tokenize(line);
+
+        while !tokens.is_empty() && tokens[0].text.ends_with(':') {
+            let token = tokens.remove(0);
+            let label = &token.text[..token.text.len() - 1];
+
+            if !is_label(label) {
+                asm.error_at(ErrorKind::InvalidLabel, format!("invalid label name `{}`", label), line_no, &token);
+            } else if symbols.insert(label, address as u16).is_some() {
+                asm.error_at(ErrorKind::DuplicateSymbol, format!("symbol `{}` is defined more than once", label), line_no, &token);
+            }
+        }
+
+        if tokens.is_empty() {
             continue;
         }
 
-        let arg1 = structure[1];
-        let arg2 = structure[2];
+        let first = tokens[0];
 
-        match command {
-            "PNT" => {
-                prg_out.push(1);
-                prg_out.push(match get_reg(arg1) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
-                prg_out.push(match get_reg(arg2) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
+        let item = match tokens[0].text {
+            ".word" => {
+                if tokens.len() == 1 {
+                    let column = tokens[0].column + tokens[0].len();
+                    asm.error(ErrorKind::OperandCount, String::from("`.word` expects at least 1 operand"), line_no, column, 1);
+                    continue;
+                }
+                Item::Words(tokens[1..].to_vec())
             },
-            "SAV" => {
-                prg_out.push(2);
-                prg_out.push(match get_reg(arg1) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
-                prg_out.push(match get_reg(arg2) {
+            ".string" | ".pstring" => {
+                if !asm.operand_count(line_no, &tokens, 1) {
+                    continue;
+                }
+                let chars = match asm.string(line_no, &tokens[1]) {
                     Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
+                    None => continue,
+                };
+
+                if tokens[0].text == ".string" {
+                    Item::Data(chars)
+                } else if chars.iter().any(|d| *d > 0xFF) {
+                    asm.error_at(ErrorKind::InvalidString, String::from("packed strings may only contain byte-sized characters"), line_no, &tokens[1]);
+                    continue;
+                } else {
+                    Item::Data(chars.chunks(2)
+                        .map(|d| {
+                            (d[0] << 8) | d.get(1).cloned().unwrap_or(0)
+                        })
+                        .collect())
+                }
             },
-            "SET" => {
-                prg_out.push(3);
-                prg_out.push(match get_reg(arg1) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
-                prg_out.push(match arg2.parse::<u16>() {
-                    Ok(x) => x,
-                    Err(x) => return Err(x.to_string()),
-                });
-            },
-            "CPY" => {
-                prg_out.push(4);
-                prg_out.push(match get_reg(arg1) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
-                prg_out.push(match get_reg(arg2) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
+            ".zero" => {
+                if !asm.operand_count(line_no, &tokens, 1) {
+                    continue;
+                }
+                match asm.value(line_no, &tokens[1], &symbols) {
+                    Some(x) => Item::Data(vec![0; x as usize]),
+                    None => continue,
+                }
             },
-            "ADD" => {
-                prg_out.push(5);
-                prg_out.push(match get_reg(arg1) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
-                prg_out.push(match get_reg(arg2) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
+            ".org" => {
+                if !asm.operand_count(line_no, &tokens, 1) {
+                    continue;
+                }
+                match asm.value(line_no, &tokens[1], &symbols) {
+                    Some(x) if (x as u32) < address => {
+                        asm.error_at(ErrorKind::InvalidOrigin, format!("cannot move the origin back from {} to {}", address, x), line_no, &tokens[1]);
+                        continue;
+                    },
+                    Some(x) => Item::Origin(x),
+                    None => continue,
+                }
             },
-            "SUB" => {
-                prg_out.push(6);
-                prg_out.push(match get_reg(arg1) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
-                prg_out.push(match get_reg(arg2) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
+            ".equ" => {
+                if !asm.operand_count(line_no, &tokens, 2) {
+                    continue;
+                }
+                let name = tokens[1].text;
+
+                if !is_label(name) {
+                    asm.error_at(ErrorKind::InvalidLabel, format!("invalid constant name `{}`", name), line_no, &tokens[1]);
+                } else if let Some(x) = asm.value(line_no, &tokens[2], &symbols) {
+                    if symbols.insert(name, x).is_some() {
+                        asm.error_at(ErrorKind::DuplicateSymbol, format!("symbol `{}` is defined more than once", name), line_no, &tokens[1]);
+                    }
+                }
+                continue;
             },
-            "XOR" => {
-                prg_out.push(7);
-                prg_out.push(match get_reg(arg1) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
-                prg_out.push(match get_reg(arg2) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
+            ".entry" => {
+                if !asm.operand_count(line_no, &tokens, 1) {
+                    continue;
+                }
+                if let Some(x) = entry_line {
+                    asm.error_at(ErrorKind::DuplicateEntry, format!("the entry point is already set on line {}", x), line_no, &tokens[0]);
+                    continue;
+                }
+                entry_line = Some(line_no);
+                Item::Entry(tokens[1])
             },
-            "NOR" => {
-                prg_out.push(8);
-                prg_out.push(match get_reg(arg1) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
-                prg_out.push(match get_reg(arg2) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
+            ".init" => {
+                if !asm.operand_count(line_no, &tokens, 2) {
+                    continue;
+                }
+                Item::Init(tokens[1], tokens[2])
             },
-            "AND" => {
-                prg_out.push(9);
-                prg_out.push(match get_reg(arg1) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
-                prg_out.push(match get_reg(arg2) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
+            x if x.starts_with('.') => {
+                asm.error_at(ErrorKind::UnknownDirective, format!("did not recognise directive `{}`", x), line_no, &tokens[0]);
+                continue;
             },
-            "LST" => {
-                prg_out.push(10);
-                prg_out.push(match get_reg(arg1) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
-                prg_out.push(match get_reg(arg2) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
+            _ => Item::Instruction(tokens),
+        };
+
+        let end = match &item {
+            Item::Instruction(x) => address + Opcode::from_mnemonic(x[0].text).map(|d| d.size()).unwrap_or(0) as u32,
+            Item::Words(x) => address + x.len() as u32,
+            Item::Data(x) => address + x.len() as u32,
+            Item::Origin(x) => *x as u32,
+            Item::Entry(_) | Item::Init(..) => address,
+        };
+
+        // only the first statement to run past the end is reported
+        if end > 0x10000 && address <= 0x10000 {
+            asm.error_at(ErrorKind::OutOfRange, String::from("the program runs past the end of memory"), line_no, &first);
+        }
+        address = end;
+
+        statements.push(Statement {
+            line: line_no,
+            item,
+        });
+    }
+
+    // second pass: emit code, resolving label references
+    let mut prg_out = vec![];
+    let mut entry = None;
+    let mut registers = vec![];
+
+    for Statement { line, item } in statements {
+        let tokens = match item {
+            Item::Instruction(x) => x,
+            Item::Words(x) => {
+                for token in x.iter() {
+                    let word = asm.value(line, token, &symbols).unwrap_or(0);
+                    prg_out.push(word);
+                }
+                continue;
             },
-            "JNZ" => {
-                prg_out.push(11);
-                prg_out.push(match get_reg(arg1) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
-                prg_out.push(match get_reg(arg2) {
-                    Some(x) => x,
-                    None => return Err(format!("Did not recognise register: `{}` at line {}.", arg1, i)),
-                });
+            Item::Data(x) => {
+                prg_out.extend(x);
+                continue;
+            },
+            Item::Origin(x) => {
+                prg_out.resize(x as usize, 0);
+                continue;
+            },
+            Item::Entry(x) => {
+                entry = asm.value(line, &x, &symbols);
+                continue;
             },
-            x => return Err(format!("Did not recognise instruction: `{}` at line {}.", command, i)),
+            Item::Init(register, value) => {
+                let r = get_reg(register.text);
+                if r.is_none() {
+                    asm.error_at(ErrorKind::UnknownRegister, format!("did not recognise register `{}`", register.text), line, &register);
+                }
+                if let (Some(r), Some(value)) = (r, asm.value(line, &value, &symbols)) {
+                    registers.push((r, value));
+                }
+                continue;
+            },
+        };
+
+        let command = tokens[0];
+
+        let opcode = match Opcode::from_mnemonic(command.text) {
+            Some(x) => x,
+            None => {
+                asm.error_at(ErrorKind::UnknownInstruction, format!("did not recognise instruction `{}`", command.text), line, &command);
+                continue;
+            },
+        };
+
+        let operands = opcode.operands();
+
+        if !asm.operand_count(line, &tokens, operands.len()) {
+            continue;
+        }
+
+        prg_out.push(opcode.code());
+
+        for (arg, operand) in tokens[1..].iter().zip(operands.iter()) {
+            prg_out.push(match operand {
+                OperandKind::Register => match get_reg(arg.text) {
+                    Some(x) => x,
+                    None => {
+                        asm.error_at(ErrorKind::UnknownRegister, format!("did not recognise register `{}`", arg.text), line, arg);
+                        0
+                    },
+                },
+                OperandKind::Immediate => asm.value(line, arg, &symbols).unwrap_or(0),
+            });
         }
     }
-    Ok(prg_out)
+
+    if asm.errors.is_empty() {
+        Ok(Assembly {
+            words: prg_out,
+            symbols: symbols.into_iter()
+                .map(|(k, v)| {
+                    (k.to_string(), v)
+                })
+                .collect(),
+            entry,
+            registers,
+        })
+    } else {
+        asm.errors.sort_by_key(|d| (d.line, d.column));
+        Err(asm.errors)
+    }
 }
 
 pub fn force_u8(v: Vec<u16>) -> Vec<u8> {
     v.iter()
         .flat_map(|d| {
-            d.to_be_bytes()
-                .into_iter()
-                .map(|x| {
-                    *x
-                })
-                .collect::<Vec<u8>>()
+            d.to_be_bytes().to_vec()
         })
         .collect()
 }
 
+/// Assembles `i` into `o`. On failure the returned string holds every
+/// diagnostic, already rendered against the source.
 pub fn compile(i: &Path, o: &Path) -> Result<(), String> {
     let string = match read_to_string(i) {
         Ok(x) => x,
         Err(x) => return Err(x.to_string()),
     };
 
-    let data = compile_raw(string)?;
+    let data = match compile_raw(&string, &i.to_string_lossy()) {
+        Ok(x) => x,
+        Err(x) => {
+            let rendered: Vec<String> = x.iter()
+                .map(|d| {
+                    d.render(&string)
+                })
+                .collect();
+
+            return Err(format!("{}\naborting due to {} previous error(s)", rendered.join("\n"), x.len()));
+        },
+    };
     let bytes = force_u8(data);
 
     match write(o, bytes) {
@@ -178,32 +483,286 @@ pub fn compile(i: &Path, o: &Path) -> Result<(), String> {
     Ok(())
 }
 
-fn get_reg(s: &str) -> Option<u16> {
-    Some(match s {
-        "out" => {
-            0
-        },
-        "count" => {
-            1
-        },
-        "a" => {
-            2
-        },
-        "b" => {
-            3
-        },
-        "c" => {
-            4
-        },
-        "d" => {
-            5
-        },
-        "e" => {
-            6
-        },
-        "f" => {
-            7
-        },
-        _ => return None,
-    })
-}
\ No newline at end of file
+fn is_label(s: &str) -> bool {
+    let mut chars = s.chars();
+
+    match chars.next() {
+        Some(x) if x.is_ascii_alphabetic() || x == '_' || x == '.' => {},
+        _ => return false,
+    }
+
+    chars.all(|x| x.is_ascii_alphanumeric() || x == '_' || x == '.')
+}
+
+/// Splits a line into tokens separated by whitespace or commas, dropping any
+/// `;` or `#` comment. Quoted literals and parenthesised expressions are kept
+/// whole, and a label definition (`name:`) always ends its token, so
+/// `loop:SET a 1` reads as `loop: SET a 1`. Tokens separated only by
+/// whitespace are joined back together around binary operators, so that
+/// `SET a msg + 3` has two operands.
+fn tokenize(line: &str) -> Vec<Token<'_>> {
+    // (start byte, end byte, start column) of each raw token
+    let mut spans: Vec<(usize, usize, usize)> = vec![];
+    let mut start: Option<(usize, usize)> = None;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut depth = 0;
+    let mut end = line.len();
+
+    for (column, (i, d)) in line.char_indices().enumerate() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if d == '\\' {
+                escaped = true;
+            } else if d == q {
+                quote = None;
+            }
+            continue;
+        }
+
+        if depth > 0 && d != ';' && d != '#' {
+            if d == '(' {
+                depth += 1;
+            } else if d == ')' {
+                depth -= 1;
+            }
+            continue;
+        }
+
+        if d == ';' || d == '#' || d == ',' || d.is_whitespace() {
+            if let Some((x, col)) = start.take() {
+                spans.push((x, i, col));
+            }
+            if d == ';' || d == '#' {
+                end = i;
+                break;
+            }
+            continue;
+        }
+
+        if start.is_none() {
+            start = Some((i, column));
+        }
+
+        if d == '"' || d == '\'' {
+            quote = Some(d);
+        } else if d == '(' {
+            depth += 1;
+        } else if d == ':' {
+            if let Some((x, col)) = start.take() {
+                spans.push((x, i + 1, col));
+            }
+        }
+    }
+
+    if let Some((x, col)) = start {
+        spans.push((x, end, col));
+    }
+
+    let mut merged: Vec<(usize, usize, usize)> = vec![];
+
+    for span in spans {
+        if let Some(last) = merged.last_mut() {
+            let left = &line[last.0..last.1];
+            let right = &line[span.0..span.1];
+            let joined = !line[last.1..span.0].contains(',')
+                && !left.ends_with(':')
+                && (left.ends_with(['+', '-', '*', '/', '&', '|', '^', '<', '>', '=', '!', '~'])
+                    || right.starts_with(['+', '*', '/', '&', '|', '^', '<', '>', '='])
+                    || right.starts_with("!=")
+                    || right == "-");
+
+            if joined {
+                last.1 = span.1;
+                continue;
+            }
+        }
+        merged.push(span);
+    }
+
+    merged.into_iter()
+        .map(|(x, y, col)| {
+            Token { text: &line[x..y], column: col + 1 }
+        })
+        .collect()
+}
+
+/// Resolves the escapes in the body of a string literal: `\n`, `\r`, `\t`,
+/// `\0`, `\\`, `\"`, `\'` and `\xHH`.
+pub fn unescape(s: &str) -> Result<Vec<u16>, String> {
+    let mut out = vec![];
+    let mut chars = s.chars();
+
+    while let Some(d) = chars.next() {
+        if d != '\\' {
+            if d as u32 > 0xFFFF {
+                return Err(format!("character `{}` does not fit in a word", d));
+            }
+            out.push(d as u16);
+            continue;
+        }
+
+        out.push(match chars.next() {
+            Some('n') => 10,
+            Some('r') => 13,
+            Some('t') => 9,
+            Some('0') => 0,
+            Some('\\') => '\\' as u16,
+            Some('"') => '"' as u16,
+            Some('\'') => '\'' as u16,
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u16::from_str_radix(&hex, 16) {
+                    Ok(x) if hex.len() == 2 => x,
+                    _ => return Err(format!("invalid escape `\\x{}`", hex)),
+                }
+            },
+            Some(x) => return Err(format!("unknown escape `\\{}`", x)),
+            None => return Err(String::from("unterminated escape at end of string")),
+        });
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(source: &str) -> Result<Vec<u16>, Vec<AsmError>> {
+        compile_raw(source, "test.rasm")
+    }
+
+    fn kinds(source: &str) -> Vec<ErrorKind> {
+        words(source).unwrap_err().iter().map(|x| x.kind).collect()
+    }
+
+    fn texts(line: &str) -> Vec<&str> {
+        tokenize(line).iter().map(|x| x.text).collect()
+    }
+
+    #[test]
+    fn labels_resolve_to_addresses() {
+        let source = "start: SET a end\nloop:\nSET b loop\nHLT\nend: HLT\n";
+        assert_eq!(words(source).unwrap(), [3, 2, 7, 3, 3, 3, 0, 0]);
+        assert_eq!(words("SET a later\nlater: HLT\n").unwrap(), [3, 2, 3, 0]);
+    }
+
+    #[test]
+    fn bad_labels() {
+        assert_eq!(kinds("SET a nowhere\n"), [ErrorKind::UndefinedSymbol]);
+        assert_eq!(kinds("x: HLT\nx: HLT\n"), [ErrorKind::DuplicateSymbol]);
+        assert_eq!(kinds("1x: HLT\n"), [ErrorKind::InvalidLabel]);
+    }
+
+    #[test]
+    fn comments_and_whitespace() {
+        assert_eq!(texts("  SET\ta   1 ; one"), ["SET", "a", "1"]);
+        assert_eq!(texts("HLT # done; really"), ["HLT"]);
+        assert_eq!(texts("; nothing"), Vec::<&str>::new());
+        assert_eq!(texts("loop:SET a 1"), ["loop:", "SET", "a", "1"]);
+
+        let source = "; a program\n\n  \tSET\ta   5  # tabs and spaces\n   \nloop:   ADD  a a\n\tHLT\n";
+        assert_eq!(words(source).unwrap(), [3, 2, 5, 5, 2, 2, 0]);
+    }
+
+    #[test]
+    fn positions_are_one_based() {
+        let errors = words("HLT\n  SET a  nowhere\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].line, errors[0].column, errors[0].span), (2, 10, 7));
+        assert_eq!(errors[0].to_string(), "test.rasm:2:10: error[A003]: use of undefined symbol `nowhere`");
+
+        let rendered = errors[0].render("HLT\n  SET a  nowhere\n");
+        assert!(rendered.ends_with("2 |   SET a  nowhere\n  |          ^^^^^^^\n"));
+    }
+
+    #[test]
+    fn operand_counts_are_checked() {
+        let errors = words("SET a\nHLT a b\n").unwrap_err();
+        assert_eq!((errors[0].kind, errors[0].line, errors[0].column, errors[0].span), (ErrorKind::OperandCount, 1, 6, 1));
+        assert_eq!((errors[1].kind, errors[1].line, errors[1].column, errors[1].span), (ErrorKind::OperandCount, 2, 5, 3));
+    }
+
+    #[test]
+    fn every_error_is_reported() {
+        let source = "ADD a q\nFOO a b\nx: HLT\nx: SET a 12ab\n";
+        assert_eq!(kinds(source), [
+            ErrorKind::UnknownRegister,
+            ErrorKind::UnknownInstruction,
+            ErrorKind::DuplicateSymbol,
+            ErrorKind::InvalidNumber,
+        ]);
+    }
+
+    #[test]
+    fn data_directives() {
+        let source = "SET a msg\nmsg: .string \"Hi, \\\"x\\\"\\n\"\n.pstring \"abc\"\n.word 7, msg, end\n.zero 2\nend: HLT\n";
+        assert_eq!(words(source).unwrap(), [
+            3, 2, 3,
+            72, 105, 44, 32, 34, 120, 34, 10,
+            0x6162, 0x6300,
+            7, 3, 18,
+            0, 0,
+            0,
+        ]);
+    }
+
+    #[test]
+    fn origins_pad_with_zeros() {
+        assert_eq!(words("HLT\n.org 4\nhere: SET a here\n").unwrap(), [0, 0, 0, 0, 3, 2, 4]);
+        assert_eq!(words(".org 0\nHLT\n").unwrap(), [0]);
+        assert_eq!(kinds("SET a 1\n.org 2\n"), [ErrorKind::InvalidOrigin]);
+    }
+
+    #[test]
+    fn programs_must_fit_in_memory() {
+        assert_eq!(words(".org 0xFFFF\nHLT\n").unwrap().len(), 0x10000);
+        assert_eq!(kinds(".zero 0xFFFF\n.word 1, 2\n"), [ErrorKind::OutOfRange]);
+        assert_eq!(kinds(".org 0xFFFF\n.word 1\n.word 2\n.word 3\n"), [ErrorKind::OutOfRange]);
+
+        let errors = words(".org 0xFFFE\nSET a 1\n").unwrap_err();
+        assert_eq!((errors[0].line, errors[0].column), (2, 1));
+    }
+
+    #[test]
+    fn bad_directives() {
+        assert_eq!(kinds(".string abc\n.string \"\\q\"\n.pstring \"\u{100}\"\n"), [ErrorKind::InvalidString; 3]);
+        assert_eq!(kinds(".word\n.zero\n.org 1 2\n"), [ErrorKind::OperandCount; 3]);
+        assert_eq!(kinds(".bytes 1\n.zero 12ab\n"), [ErrorKind::UnknownDirective, ErrorKind::InvalidNumber]);
+    }
+
+    #[test]
+    fn operators_join_operands() {
+        assert_eq!(texts("SET a msg - 3"), ["SET", "a", "msg - 3"]);
+        assert_eq!(texts("SET a msg + 3 ; comment"), ["SET", "a", "msg + 3"]);
+        assert_eq!(texts("SET a 1 << 4"), ["SET", "a", "1 << 4"]);
+        assert_eq!(texts("SET a (msg + 1) * 2"), ["SET", "a", "(msg + 1) * 2"]);
+        assert_eq!(texts(".word 5 -3"), [".word", "5", "-3"]);
+        assert_eq!(texts(".word 5, - 3"), [".word", "5", "- 3"]);
+        assert_eq!(texts("SET a len ^ 1 != 0"), ["SET", "a", "len ^ 1 != 0"]);
+        assert_eq!(texts(".word 1 !0"), [".word", "1", "!0"]);
+    }
+
+    #[test]
+    fn quotes_are_kept_whole() {
+        assert_eq!(texts(".string \"a, b; c\" # done"), [".string", "\"a, b; c\""]);
+        assert_eq!(texts("SET a ';'"), ["SET", "a", "';'"]);
+        assert_eq!(tokenize("  SET a 1").iter().map(|x| x.column).collect::<Vec<_>>(), [3, 7, 9]);
+    }
+
+    #[test]
+    fn words_with_signs() {
+        let assembly = assemble(".word 5 -3\n.word 5 - 3, -1\n", "test").ok().unwrap();
+        assert_eq!(assembly.words, [5, 0xFFFD, 2, 0xFFFF]);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(unescape("a\\n\\t\\0\\\\\\\"\\x41"), Ok(vec![97, 10, 9, 0, 92, 34, 65]));
+        assert!(unescape("\\x4").is_err());
+        assert!(unescape("\\z").is_err());
+        assert!(unescape("\\").is_err());
+    }
+}
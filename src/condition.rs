@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use RISC_16_bit::isa::get_reg;
+use crate::expr::{parse, Expr, Node, Scope};
+
+/// A parsed debugger condition such as `f > 16100 && [8080] != 0`: an
+/// expression, as the assembler reads them, in which names may also be
+/// registers and `[address]` reads a word of memory.
+pub struct Condition(Expr);
+
+/// Replaces the symbols in `expr` with their values, leaving registers, which
+/// take precedence, to be read when it is evaluated.
+fn resolve(expr: &mut Expr, symbols: &HashMap<String, u16>) -> Result<(), String> {
+    match &mut expr.node {
+        Node::Number(_) => {},
+        Node::Name(x) if get_reg(x).is_some() => {},
+        Node::Name(x) => match symbols.get(x.as_str()) {
+            Some(y) => expr.node = Node::Number(*y as i64),
+            None => return Err(format!("`{}` is not a register or symbol", x)),
+        },
+        Node::Memory(x) | Node::Unary(_, x) => resolve(x, symbols)?,
+        Node::Binary(_, x, y) => {
+            resolve(x, symbols)?;
+            resolve(y, symbols)?;
+        },
+    }
+    Ok(())
+}
+
+/// The machine as a condition sees it.
+struct Machine<'a> {
+    registers: &'a [u16; 8],
+    memory: &'a mut dyn FnMut(u16) -> u16,
+}
+
+impl Scope for Machine<'_> {
+    fn name(&mut self, name: &str) -> Option<i64> {
+        get_reg(name).map(|x| self.registers[x as usize] as i64)
+    }
+
+    fn memory(&mut self, address: u16) -> Option<i64> {
+        Some((self.memory)(address) as i64)
+    }
+}
+
+impl Condition {
+    pub fn parse(text: &str, symbols: &HashMap<String, u16>) -> Result<Condition, String> {
+        let mut expr = parse(text).map_err(|x| x.message)?;
+        resolve(&mut expr, symbols)?;
+        Ok(Condition(expr))
+    }
+
+    /// Evaluates the condition, failing if it overflows or divides by zero.
+    pub fn evaluate(&self, registers: &[u16; 8], memory: &mut dyn FnMut(u16) -> u16) -> Result<i64, String> {
+        let mut machine = Machine {
+            registers,
+            memory,
+        };
+        self.0.evaluate(&mut machine).map_err(|x| x.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(text: &str) -> Result<i64, String> {
+        let symbols: HashMap<String, u16> = [(String::from("end"), 40)].iter().cloned().collect();
+        let registers = [0, 1, 2, 3, 4, 5, 6, 16100];
+        let condition = Condition::parse(text, &symbols)?;
+        condition.evaluate(&registers, &mut |x| x.wrapping_mul(2))
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(value("1 + 2 * 3"), Ok(7));
+        assert_eq!(value("1 | 6 ^ 3 & 1"), Ok(7));
+        assert_eq!(value("a + 1 == 3 && b < c || 0"), Ok(1));
+        assert_eq!(value("-(1 << 3) >> 1"), Ok(-4));
+        assert_eq!(value("!count + ~0"), Ok(-1));
+    }
+
+    #[test]
+    fn registers_symbols_and_memory() {
+        assert_eq!(value("f > 16100 && [8080] != 0"), Ok(0));
+        assert_eq!(value("f >= 16100 && [8080] != 0"), Ok(1));
+        assert_eq!(value("[end + a]"), Ok(84));
+        assert_eq!(value("[[3]]"), Ok(12));
+        assert_eq!(value("'A' == 65 && (b ^ 1) == 2"), Ok(1));
+        assert_eq!(value("!a || [c] == end"), Ok(0));
+        assert!(value("0x10 / 0").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(value("a +").is_err());
+        assert!(value("(a").is_err());
+        assert!(value("[a").is_err());
+        assert!(value("a b").is_err());
+        assert!(value("nope == 1").is_err());
+        assert!(value("12ab").is_err());
+        assert!(value("a $ b").is_err());
+    }
+}
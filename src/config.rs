@@ -0,0 +1,143 @@
+use std::fs::read_to_string;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use RISC_16_bit::bus::{Bus, Device, Dma};
+use RISC_16_bit::console::{ConsoleIn, ConsoleOut, Input};
+use RISC_16_bit::disk::Disk;
+use RISC_16_bit::interrupt::{Controller, Interrupts, DISK_IRQ, INPUT_IRQ, TIMER_IRQ};
+use RISC_16_bit::ram::Ram;
+use RISC_16_bit::rng::Rng;
+use RISC_16_bit::rom::Rom;
+use RISC_16_bit::timer::Timer;
+use crate::expr::parse_number;
+use crate::read_image;
+
+/// What devices share with the rest of the machine.
+pub struct Context {
+    /// Where input devices read from.
+    pub input: Input,
+    pub interrupts: Interrupts,
+    /// What random number generators start from.
+    pub seed: u16,
+}
+
+fn number(text: &str) -> Result<u32, String> {
+    match parse_number(text) {
+        Some(x) if (0..=65536).contains(&x) => Ok(x as u32),
+        Some(x) => Err(format!("{} is not an address or length", x)),
+        None => Err(format!("`{}` is not a number", text)),
+    }
+}
+
+/// Builds the device named by `kind` from the arguments following its base,
+/// returning how many addresses it takes up. Devices that copy to and from
+/// memory send their transfers to `dma`.
+fn device(kind: &str, args: &[&str], context: &Context, dma: Sender<Dma>) -> Result<(u32, Device), String> {
+    let expect = |count: usize, usage: &str| {
+        if args.len() == count {
+            Ok(())
+        } else {
+            Err(format!("expected `{}`", usage))
+        }
+    };
+
+    match kind {
+        "ram" => {
+            expect(1, "ram <base> <length>")?;
+            let len = number(args[0])?;
+            Ok((len, Box::new(Ram::new(len as usize))))
+        },
+        "console" => {
+            expect(0, "console <base>")?;
+            Ok((2, Box::new(ConsoleOut::new())))
+        },
+        "input" => {
+            expect(0, "input <base>")?;
+            let mut device = ConsoleIn::new(context.input.clone());
+            device.connect(context.interrupts.line(INPUT_IRQ));
+            Ok((2, Box::new(device)))
+        },
+        "timer" => {
+            expect(0, "timer <base>")?;
+            let mut device = Timer::new();
+            device.connect(context.interrupts.line(TIMER_IRQ));
+            Ok((4, Box::new(device)))
+        },
+        "interrupts" => {
+            expect(0, "interrupts <base>")?;
+            Ok((3, Box::new(Controller(context.interrupts.clone()))))
+        },
+        "rom" => {
+            expect(1, "rom <base> <image>")?;
+            // a header's entry point and registers are for `--boot-rom`, not here
+            let device = Rom::new(read_image(args[0])?.words);
+            Ok((device.len() as u32, Box::new(device)))
+        },
+        "rng" => {
+            expect(0, "rng <base>")?;
+            Ok((2, Box::new(Rng::new(context.seed))))
+        },
+        "disk" => {
+            expect(1, "disk <base> <image>")?;
+            let mut device = match Disk::open(Path::new(args[0]), dma) {
+                Ok(x) => x,
+                Err(x) => return Err(format!("{}: {}", args[0], x)),
+            };
+            device.connect(context.interrupts.line(DISK_IRQ));
+            Ok((4, Box::new(device)))
+        },
+        x => Err(format!("unknown device `{}`", x)),
+    }
+}
+
+/// Builds a bus from a machine description, which lists one device per line
+/// as its kind, its base address and then any arguments that kind takes:
+///
+/// ```text
+/// # kind     base   arguments
+/// ram        0      8080
+/// console    8080
+/// input      8082
+/// timer      8084
+/// interrupts 8088
+/// disk       8092   overlays.img
+/// rng        8096
+/// ram        8128   55360
+/// rom        0xF800 boot.rex
+/// ```
+///
+/// Everything after a `#` is a comment.
+pub fn parse(text: &str, file: &str, context: &Context) -> Result<Bus, String> {
+    let mut bus = Bus::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let result = match words.len() {
+            0 => continue,
+            1 => Err(format!("`{}` expects a base address", words[0])),
+            _ => number(words[1])
+                .and_then(|base| {
+                    if base > 0xFFFF {
+                        return Err(format!("{} is not an address", base));
+                    }
+                    let (len, device) = device(words[0], &words[2..], context, bus.dma())?;
+                    bus.attach(words[0], base as u16, len, device)
+                }),
+        };
+
+        if let Err(x) = result {
+            return Err(format!("{}:{}: {}", file, index + 1, x));
+        }
+    }
+
+    Ok(bus)
+}
+
+pub fn load(path: &str, context: &Context) -> Result<Bus, String> {
+    match read_to_string(path) {
+        Ok(x) => parse(&x, path, context),
+        Err(x) => Err(format!("{}: {}", path, x)),
+    }
+}
@@ -0,0 +1,248 @@
+use modVM::*;
+use modVM::Query::*;
+use modVM::Response::*;
+use crate::bus::Inspect;
+use std::io::{stdin, stdout, Read, Write};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+use crate::interrupt::InterruptLine;
+
+/// A two word console mailbox: a flag at offset 0 and data at offset 1.
+///
+/// Each cycle, a flag of 2 prints both bytes of the data, high byte first,
+/// and any other nonzero flag prints just the low byte. The flag is then
+/// cleared.
+pub struct ConsoleOut {
+    flag: u16,
+    data: u16,
+}
+
+impl ConsoleOut {
+    pub fn new() -> ConsoleOut {
+        ConsoleOut {
+            flag: 0,
+            data: 0,
+        }
+    }
+}
+
+impl Default for ConsoleOut {
+    fn default() -> ConsoleOut {
+        ConsoleOut::new()
+    }
+}
+
+impl Peripheral<u16> for ConsoleOut {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            model: String::from("Console Out v.0.0.0"),
+        }
+    }
+
+    fn handle(&mut self, incoming: Query<u16>) -> Result<Response<u16>, u16> {
+        Ok(match incoming {
+            LoadRequest(0) => Data(self.flag),
+            LoadRequest(1) => Data(self.data),
+            SaveRequest(x, 0) => {
+                self.flag = x;
+                Good
+            },
+            SaveRequest(x, 1) => {
+                self.data = x;
+                Good
+            },
+            _ => Fail(0),
+        })
+    }
+
+    fn cycle(&mut self) -> Result<(), u16> {
+        let [upper, lower] = self.data.to_be_bytes();
+
+        if self.flag == 2 {
+            print!("{}{}", upper as char, lower as char);
+            self.flag = 0;
+        } else if self.flag != 0 {
+            print!("{}", lower as char);
+            self.flag = 0;
+        } else {
+            return Ok(());
+        }
+
+        let _ = stdout().flush();
+        Ok(())
+    }
+}
+
+/// Where `ConsoleIn` gets its bytes from.
+#[derive(Debug, Clone)]
+pub enum Input {
+    /// The host's stdin, read from a background thread started on first use.
+    Stdin,
+    /// A fixed sequence of bytes, such as a file or a scripted string.
+    Bytes(Vec<u8>),
+}
+
+/// A two word input device: a status word at offset 0 and data at offset 1.
+///
+/// Loading the status never blocks. It reads 1 when a byte is held in the
+/// data word, 2 once the input has ended and 0 while waiting for more. Saving
+/// any nonzero value to the status instead blocks until a byte arrives or the
+/// input ends.
+///
+/// Loading the data word gives the held byte, or 0xFFFF when there is none,
+/// without consuming it. Saving anything to the data word consumes it, so
+/// that the next byte can arrive.
+///
+/// When connected to an interrupt line, the device watches for input every
+/// cycle and raises the line as each byte arrives.
+pub struct ConsoleIn {
+    input: Input,
+    bytes: Option<Receiver<u8>>,
+    held: Option<u8>,
+    ended: bool,
+    line: Option<InterruptLine>,
+}
+
+impl ConsoleIn {
+    pub fn new(input: Input) -> ConsoleIn {
+        ConsoleIn {
+            input,
+            bytes: None,
+            held: None,
+            ended: false,
+            line: None,
+        }
+    }
+
+    pub fn connect(&mut self, line: InterruptLine) {
+        self.line = Some(line);
+    }
+
+    fn receiver(&mut self) -> &Receiver<u8> {
+        let input = &self.input;
+
+        self.bytes.get_or_insert_with(|| {
+            let (sender, receiver) = channel();
+
+            match input {
+                Input::Stdin => {
+                    thread::spawn(move || {
+                        for byte in stdin().lock().bytes() {
+                            match byte {
+                                Ok(x) if sender.send(x).is_ok() => {},
+                                _ => break,
+                            }
+                        }
+                    });
+                },
+                Input::Bytes(x) => {
+                    for byte in x {
+                        let _ = sender.send(*byte);
+                    }
+                },
+            }
+
+            receiver
+        })
+    }
+
+    /// Fetches the next byte into `held` if there is room for it, waiting for
+    /// it only if `block` is set.
+    fn fetch(&mut self, block: bool) {
+        if self.held.is_some() || self.ended {
+            return;
+        }
+
+        let receiver = self.receiver();
+        let next = if block {
+            receiver.recv().map_err(|_| TryRecvError::Disconnected)
+        } else {
+            receiver.try_recv()
+        };
+
+        match next {
+            Ok(x) => self.held = Some(x),
+            Err(TryRecvError::Disconnected) => self.ended = true,
+            Err(TryRecvError::Empty) => {},
+        }
+    }
+}
+
+impl Peripheral<u16> for ConsoleIn {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            model: String::from("Console In v.0.0.0"),
+        }
+    }
+
+    fn handle(&mut self, incoming: Query<u16>) -> Result<Response<u16>, u16> {
+        Ok(match incoming {
+            LoadRequest(0) => {
+                self.fetch(false);
+                Data(match (self.held, self.ended) {
+                    (Some(_), _) => 1,
+                    (None, true) => 2,
+                    (None, false) => 0,
+                })
+            },
+            LoadRequest(1) => Data(self.held.map(|d| d as u16).unwrap_or(0xFFFF)),
+            SaveRequest(x, 0) => {
+                if x != 0 {
+                    self.fetch(true);
+                }
+                Good
+            },
+            SaveRequest(_, 1) => {
+                self.held = None;
+                Good
+            },
+            _ => Fail(0),
+        })
+    }
+
+    fn cycle(&mut self) -> Result<(), u16> {
+        if self.line.is_none() || self.held.is_some() {
+            return Ok(());
+        }
+
+        self.fetch(false);
+        if let (Some(line), Some(_)) = (&self.line, self.held) {
+            line.raise();
+        }
+
+        Ok(())
+    }
+}
+
+impl Inspect for ConsoleOut {
+    fn peek(&self, offset: u16) -> Option<u16> {
+        match offset {
+            0 => Some(self.flag),
+            1 => Some(self.data),
+            _ => None,
+        }
+    }
+
+    fn poke(&mut self, offset: u16, value: u16) -> bool {
+        match offset {
+            0 => self.flag = value,
+            1 => self.data = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl Inspect for ConsoleIn {
+    fn peek(&self, offset: u16) -> Option<u16> {
+        // a byte only shows once the program or `cycle` has fetched it
+        match (offset, self.held) {
+            (0, Some(_)) => Some(1),
+            (0, None) if self.ended => Some(2),
+            (0, None) => Some(0),
+            (1, Some(x)) => Some(x as u16),
+            (1, None) => Some(0xFFFF),
+            _ => None,
+        }
+    }
+}
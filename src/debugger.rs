@@ -0,0 +1,628 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{stdin, stdout, BufRead, Write};
+use modVM::{Peripheral, Query, Query::*, Response, Response::*};
+use RISC_16_bit::*;
+use RISC_16_bit::bus::{Bus, Inspect};
+use RISC_16_bit::isa::{get_reg, REGISTERS};
+use RISC_16_bit::watch::{WatchHit, WatchKind, Watched, Watchpoint};
+use crate::condition::Condition;
+use crate::disasm::{disassemble_from, DisasmLine};
+use crate::expr::evaluate;
+
+/// Why `Session::run` gave control back.
+pub enum Stop {
+    Breakpoint(u16),
+    Watch(Vec<WatchHit>),
+    /// The predicate with this index became true.
+    Predicate(usize),
+    Outcome(Outcome),
+    /// Running backwards used up the recorded history.
+    StartOfHistory,
+}
+
+/// How many steps the debugger remembers for running backwards.
+const HISTORY_LIMIT: usize = 100_000;
+
+/// What one step did, so that it can be undone.
+pub struct Record {
+    /// The processor before the step.
+    pub state: CpuState,
+    /// Each save as `(address, old, new)`.
+    pub writes: Vec<(u16, u16, u16)>,
+    /// Each load as `(address, value)`, including instruction fetches.
+    pub reads: Vec<(u16, u16)>,
+}
+
+/// A condition that stops the machine whenever it goes from false to true.
+pub struct Predicate {
+    pub text: String,
+    pub condition: Condition,
+    pub last: bool,
+}
+
+/// A machine driven one instruction at a time, rather than by `modVM`'s
+/// threads, along with what the debugger knows about it.
+pub struct Session {
+    pub processor: MainProcessor,
+    pub memory: Watched<Bus>,
+    pub symbols: HashMap<String, u16>,
+    /// Address breakpoints, each with an optional condition.
+    pub breakpoints: BTreeMap<u16, Option<(String, Condition)>>,
+    pub predicates: Vec<Predicate>,
+    /// The most recent steps, oldest first.
+    pub history: VecDeque<Record>,
+}
+
+/// Passes the processor's queries on to the memory, noting them in `record`.
+struct Recorder<'a> {
+    memory: &'a mut Watched<Bus>,
+    record: &'a mut Record,
+}
+
+impl MemoryPort for Recorder<'_> {
+    fn query(&mut self, query: Query<u16>) -> Response<u16> {
+        match query {
+            LoadRequest(x) => {
+                let response = Direct(&mut *self.memory).query(LoadRequest(x));
+                if let Data(y) = response {
+                    self.record.reads.push((x, y));
+                }
+                response
+            },
+            SaveRequest(x, y) => {
+                let old = self.memory.inner().peek(y);
+                let response = Direct(&mut *self.memory).query(SaveRequest(x, y));
+                if let (Good, Some(z)) = (&response, old) {
+                    self.record.writes.push((y, z, x));
+                }
+                response
+            },
+        }
+    }
+}
+
+fn holds(condition: &Condition, registers: &[u16; 8], memory: &Bus) -> bool {
+    condition.evaluate(registers, &mut |address| memory.peek(address).unwrap_or(0)).is_ok_and(|x| x != 0)
+}
+
+impl Session {
+    pub fn new(processor: MainProcessor, memory: Bus, symbols: HashMap<String, u16>) -> Session {
+        Session {
+            processor,
+            memory: Watched::new(memory),
+            symbols,
+            breakpoints: BTreeMap::new(),
+            predicates: vec![],
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.processor.registers()[1]
+    }
+
+    /// Executes one instruction, then gives the memory a cycle so that console
+    /// output appears as it would under `run`.
+    pub fn step(&mut self) -> Result<(), Outcome> {
+        let mut record = Record {
+            state: self.processor.state(),
+            writes: vec![],
+            reads: vec![],
+        };
+
+        let result = self.processor.step(&mut Recorder { memory: &mut self.memory, record: &mut record });
+        let _ = self.memory.cycle();
+
+        if result.is_ok() {
+            if self.history.len() == HISTORY_LIMIT {
+                self.history.pop_front();
+            }
+            self.history.push_back(record);
+        }
+        result
+    }
+
+    /// Undoes the most recent recorded step. Only words that can be poked are
+    /// put back, so console output and most devices' registers are not taken back.
+    pub fn reverse_step(&mut self) -> Option<Record> {
+        let record = self.history.pop_back()?;
+
+        for (address, old, _) in record.writes.iter().rev() {
+            self.memory.inner().poke(*address, *old);
+        }
+        self.processor.restore(record.state);
+        Some(record)
+    }
+
+    /// Undoes one step and checks whether anything should stop the machine
+    /// before it. Watchpoints and address breakpoints are honoured, `break if`
+    /// conditions only when running forwards.
+    pub fn reverse_advance(&mut self) -> Option<Stop> {
+        let record = match self.reverse_step() {
+            Some(x) => x,
+            None => return Some(Stop::StartOfHistory),
+        };
+
+        let mut hits = vec![];
+        for (address, value) in record.reads {
+            if let Some(watchpoint) = self.memory.watching(address, false) {
+                hits.push(WatchHit { watchpoint, address, write: false, old: value, new: value });
+            }
+        }
+        for (address, old, new) in record.writes {
+            match self.memory.watching(address, true) {
+                Some(watchpoint) if watchpoint.kind == WatchKind::Access || old != new => {
+                    hits.push(WatchHit { watchpoint, address, write: true, old, new });
+                },
+                _ => {},
+            }
+        }
+
+        if !hits.is_empty() {
+            return Some(Stop::Watch(hits));
+        }
+
+        let pc = self.pc();
+        let hit = match self.breakpoints.get(&pc) {
+            Some(None) => true,
+            Some(Some((_, x))) => holds(x, &record.state.registers, self.memory.inner()),
+            None => false,
+        };
+
+        if hit {
+            Some(Stop::Breakpoint(pc))
+        } else {
+            None
+        }
+    }
+
+    /// Runs backwards until something stops the machine or the history runs out.
+    pub fn reverse_run(&mut self) -> Stop {
+        loop {
+            if let Some(x) = self.reverse_advance() {
+                return x;
+            }
+        }
+    }
+
+    /// Finds the most recent recorded step that saved to `address`, returning
+    /// how many steps ago it was, the pc it ran at and the value it wrote.
+    pub fn last_write(&self, address: u16) -> Option<(usize, u16, u16, u16)> {
+        self.history.iter()
+            .rev()
+            .enumerate()
+            .find_map(|(ago, record)| {
+                record.writes.iter()
+                    .rev()
+                    .find(|d| d.0 == address)
+                    .map(|d| (ago + 1, record.state.registers[1], d.1, d.2))
+            })
+    }
+
+    /// Executes one instruction and checks whether anything should stop the
+    /// machine after it.
+    pub fn advance(&mut self) -> Option<Stop> {
+        if let Err(x) = self.step() {
+            return Some(Stop::Outcome(x));
+        }
+
+        let hits = self.memory.take_hits();
+        let registers = self.processor.registers();
+        let memory = self.memory.inner();
+
+        let mut stop = if hits.is_empty() { None } else { Some(Stop::Watch(hits)) };
+
+        // every predicate is re-evaluated so that each one's last state stays current
+        for (i, predicate) in self.predicates.iter_mut().enumerate() {
+            let now = holds(&predicate.condition, &registers, memory);
+            if now && !predicate.last && stop.is_none() {
+                stop = Some(Stop::Predicate(i));
+            }
+            predicate.last = now;
+        }
+
+        if stop.is_none() {
+            let pc = registers[1];
+            stop = match self.breakpoints.get(&pc) {
+                Some(None) => Some(Stop::Breakpoint(pc)),
+                Some(Some((_, x))) if holds(x, &registers, memory) => Some(Stop::Breakpoint(pc)),
+                _ => None,
+            };
+        }
+
+        stop
+    }
+
+    /// Runs until something stops the machine. A breakpoint on the current
+    /// instruction does not stop it straight away.
+    pub fn run(&mut self) -> Stop {
+        loop {
+            if let Some(x) = self.advance() {
+                return x;
+            }
+        }
+    }
+
+    pub fn condition(&self, text: &str) -> Result<Condition, String> {
+        Condition::parse(text, &self.symbols)
+    }
+
+    /// Evaluates `condition` against the machine as it is now.
+    pub fn holds(&mut self, condition: &Condition) -> bool {
+        let registers = self.processor.registers();
+        holds(condition, &registers, self.memory.inner())
+    }
+
+    /// Reads `address` without the side effects a program's load can have,
+    /// giving 0 for words that cannot be read that way.
+    pub fn read(&mut self, address: u16) -> u16 {
+        self.memory.inner().peek(address).unwrap_or(0)
+    }
+
+    pub fn write(&mut self, address: u16, value: u16) {
+        let _ = self.memory.inner().handle(SaveRequest(value, address));
+    }
+
+    /// Evaluates an address or value, which may use labels and constants
+    /// from the program.
+    pub fn evaluate(&self, text: &str) -> Result<u16, String> {
+        let symbols: HashMap<&str, u16> = self.symbols.iter()
+            .map(|(k, v)| {
+                (k.as_str(), *v)
+            })
+            .collect();
+
+        match evaluate(text, &symbols) {
+            Ok(x) if (-0x8000..=0xFFFF).contains(&x) => Ok(x as u16),
+            Ok(x) => Err(format!("{} does not fit in a word", x)),
+            Err(x) => Err(x.message),
+        }
+    }
+
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.symbols.iter()
+            .filter(|(_, v)| **v == address)
+            .map(|(k, _)| k.as_str())
+            .min()
+    }
+
+    /// Disassembles `count` instructions from `address`, starting a few
+    /// instructions earlier when some earlier start decodes cleanly onto it.
+    pub fn disassemble_around(&mut self, address: u16, count: usize) -> Vec<DisasmLine> {
+        const BEFORE: u16 = 9;
+
+        let start = (0..BEFORE)
+            .map(|d| address.saturating_sub(BEFORE - d))
+            .find(|start| {
+                let words: Vec<u16> = (*start..address).map(|d| self.read(d)).collect();
+                disassemble_from(&words, *start).iter()
+                    .all(|d| !d.data && d.address.wrapping_add(d.words.len() as u16) <= address)
+            })
+            .unwrap_or(address);
+
+        let words: Vec<u16> = (0..(address - start) as usize + count * 3)
+            .map(|d| self.read(start.wrapping_add(d as u16)))
+            .collect();
+
+        let mut lines = disassemble_from(&words, start);
+        let keep = lines.iter().position(|d| d.address >= address).unwrap_or(0) + count;
+        lines.truncate(keep);
+        lines
+    }
+}
+
+const HELP: &str = "\
+step [n]              execute n instructions (default 1)        (alias: s)
+continue              run to a breakpoint or until the machine stops (alias: c)
+break <addr> [if <cond>]
+                      set a breakpoint on an address or label    (alias: b)
+break if <cond>       stop whenever <cond> becomes true
+delete <addr>         remove a breakpoint                         (alias: d)
+delete if <n>         remove the nth `break if`
+watch <addr>[..<end>] stop when a word in the range changes
+rwatch <addr>[..<end>] stop when a word in the range is read
+awatch <addr>[..<end>] stop when a word in the range is read or written
+unwatch <n>           remove the nth watchpoint
+breakpoints           list breakpoints and watchpoints
+reverse-step [n]      undo n instructions (default 1)            (alias: rs)
+reverse-continue      run backwards to a breakpoint or watchpoint (alias: rc)
+who-wrote <addr>      find the last recorded instruction to save to addr
+history               show how many steps are recorded
+regs                  show the registers                          (alias: r)
+set <reg> <value>     change a register
+x <addr> [n]          show n words of memory (default 8)
+write <addr> <value>  change a word of memory                     (alias: w)
+list [addr]           disassemble around addr (default: the pc)   (alias: l)
+quit                                                               (alias: q)
+Addresses and values may be expressions over the program's labels, e.g. `loop+3`.
+Conditions may also use registers and `[addr]` for memory, e.g. `f > 16100 && [8080] != 0`.";
+
+fn show_registers(session: &Session) {
+    let registers = session.processor.registers();
+    let text: Vec<String> = REGISTERS.iter()
+        .zip(registers.iter())
+        .map(|(name, value)| {
+            format!("{}={}", name, value)
+        })
+        .collect();
+
+    println!("{}", text.join(" "));
+}
+
+fn show_listing(session: &mut Session, address: u16, count: usize) {
+    let pc = session.pc();
+
+    for line in session.disassemble_around(address, count) {
+        if let Some(x) = session.label_at(line.address) {
+            println!("{}:", x);
+        }
+        println!("{} {}", if line.address == pc { "=>" } else { "  " }, line.render());
+    }
+}
+
+/// Shows the instruction about to execute.
+fn show_current(session: &mut Session) {
+    let pc = session.pc();
+    let words: Vec<u16> = (0..3).map(|d| session.read(pc.wrapping_add(d))).collect();
+
+    if let Some(x) = session.label_at(pc) {
+        println!("{}:", x);
+    }
+    println!("=> {}", disassemble_from(&words, pc)[0].render());
+}
+
+fn show_watchpoint(watchpoint: &Watchpoint) -> String {
+    let kind = match watchpoint.kind {
+        WatchKind::Write => "write",
+        WatchKind::Read => "read",
+        WatchKind::Access => "access",
+    };
+
+    if watchpoint.start == watchpoint.end {
+        format!("{} {}", kind, watchpoint.start)
+    } else {
+        format!("{} {}..{}", kind, watchpoint.start, watchpoint.end)
+    }
+}
+
+fn report(session: &mut Session, stop: Stop) {
+    match stop {
+        Stop::Breakpoint(x) => println!("Breakpoint at {}.", x),
+        Stop::Watch(hits) => {
+            for hit in hits {
+                if hit.write {
+                    println!("Watchpoint ({}): [{}] {} -> {}", show_watchpoint(&hit.watchpoint), hit.address, hit.old, hit.new);
+                } else {
+                    println!("Watchpoint ({}): [{}] read {}", show_watchpoint(&hit.watchpoint), hit.address, hit.new);
+                }
+            }
+        },
+        Stop::Predicate(x) => println!("Condition `{}` became true.", session.predicates[x].text),
+        Stop::Outcome(Outcome::Halted { pc }) => println!("Machine halted at {}.", pc),
+        Stop::Outcome(Outcome::Faulted(x)) => println!("Machine FAULT: {}", x),
+        Stop::StartOfHistory => println!("Reached the start of the recorded history."),
+    }
+    show_current(session);
+}
+
+fn range(session: &Session, text: &str) -> Result<(u16, u16), String> {
+    let mut parts = text.splitn(2, "..");
+    let start = session.evaluate(parts.next().unwrap())?;
+    let end = match parts.next() {
+        Some(x) => session.evaluate(x)?,
+        None => start,
+    };
+
+    if end < start {
+        return Err(format!("{}..{} is an empty range", start, end));
+    }
+    Ok((start, end))
+}
+
+fn command(session: &mut Session, words: &[&str]) -> Result<bool, String> {
+    let arg = |i: usize| -> Result<u16, String> {
+        match words.get(i) {
+            Some(x) => session.evaluate(x),
+            None => Err(format!("`{}` expects more arguments", words[0])),
+        }
+    };
+
+    match words[0] {
+        "s" | "step" => {
+            let count = if words.len() > 1 { arg(1)? } else { 1 };
+
+            for _ in 0..count {
+                if let Some(x) = session.advance() {
+                    report(session, x);
+                    return Ok(true);
+                }
+            }
+            show_current(session);
+        },
+        "c" | "continue" => {
+            let stop = session.run();
+            report(session, stop);
+        },
+        "rs" | "reverse-step" => {
+            let count = if words.len() > 1 { arg(1)? } else { 1 };
+
+            for _ in 0..count {
+                if let Some(x) = session.reverse_advance() {
+                    report(session, x);
+                    return Ok(true);
+                }
+            }
+            show_current(session);
+        },
+        "rc" | "reverse-continue" => {
+            let stop = session.reverse_run();
+            report(session, stop);
+        },
+        "who-wrote" => {
+            let address = arg(1)?;
+            match session.last_write(address) {
+                Some((ago, pc, old, new)) => {
+                    let words: Vec<u16> = (0..3).map(|d| session.read(pc.wrapping_add(d))).collect();
+                    println!("[{}] {} -> {}, {} step(s) ago by:", address, old, new, ago);
+                    println!("   {}", disassemble_from(&words, pc)[0].render());
+                },
+                None => println!("No recorded step saved to {}.", address),
+            }
+        },
+        "history" => println!("{} step(s) recorded, up to {}.", session.history.len(), HISTORY_LIMIT),
+        "b" | "break" if words.get(1) == Some(&"if") => {
+            let text = words[2..].join(" ");
+            let condition = session.condition(&text)?;
+            let last = session.holds(&condition);
+            session.predicates.push(Predicate { text, condition, last });
+            println!("Condition {} set.", session.predicates.len() - 1);
+        },
+        "b" | "break" => {
+            let address = arg(1)?;
+            let condition = match words.get(2) {
+                Some(&"if") => {
+                    let text = words[3..].join(" ");
+                    Some((text.clone(), session.condition(&text)?))
+                },
+                Some(x) => return Err(format!("expected `if`, found `{}`", x)),
+                None => None,
+            };
+            session.breakpoints.insert(address, condition);
+            println!("Breakpoint set at {}.", address);
+        },
+        "d" | "delete" if words.get(1) == Some(&"if") => {
+            let index = arg(2)? as usize;
+            if index >= session.predicates.len() {
+                return Err(format!("no condition {}", index));
+            }
+            session.predicates.remove(index);
+        },
+        "d" | "delete" => {
+            let address = arg(1)?;
+            if session.breakpoints.remove(&address).is_none() {
+                return Err(format!("no breakpoint at {}", address));
+            }
+        },
+        "watch" | "rwatch" | "awatch" => {
+            let (start, end) = match words.get(1) {
+                Some(x) => range(session, x)?,
+                None => return Err(format!("`{}` expects an address or range", words[0])),
+            };
+            let kind = match words[0] {
+                "watch" => WatchKind::Write,
+                "rwatch" => WatchKind::Read,
+                _ => WatchKind::Access,
+            };
+            let watchpoint = Watchpoint { start, end, kind };
+            session.memory.watchpoints.push(watchpoint);
+            println!("Watchpoint {} ({}) set.", session.memory.watchpoints.len() - 1, show_watchpoint(&watchpoint));
+        },
+        "unwatch" => {
+            let index = arg(1)? as usize;
+            if index >= session.memory.watchpoints.len() {
+                return Err(format!("no watchpoint {}", index));
+            }
+            session.memory.watchpoints.remove(index);
+        },
+        "breakpoints" => {
+            let breakpoints: Vec<(u16, Option<String>)> = session.breakpoints.iter()
+                .map(|(k, v)| {
+                    (*k, v.as_ref().map(|d| d.0.clone()))
+                })
+                .collect();
+
+            for (address, condition) in breakpoints {
+                let label = match session.label_at(address) {
+                    Some(x) => format!(" ({})", x),
+                    None => String::new(),
+                };
+                match condition {
+                    Some(x) => println!("break {}{} if {}", address, label, x),
+                    None => println!("break {}{}", address, label),
+                }
+            }
+            for (i, predicate) in session.predicates.iter().enumerate() {
+                println!("condition {}: {}", i, predicate.text);
+            }
+            for (i, watchpoint) in session.memory.watchpoints.iter().enumerate() {
+                println!("watchpoint {}: {}", i, show_watchpoint(watchpoint));
+            }
+        },
+        "r" | "regs" => show_registers(session),
+        "set" => {
+            let name = words.get(1).cloned().unwrap_or("");
+            let r = match get_reg(name) {
+                Some(x) => x,
+                None => return Err(format!("did not recognise register `{}`", name)),
+            };
+            let value = arg(2)?;
+            session.processor.set_register(r as usize, value);
+        },
+        "x" => {
+            let address = arg(1)?;
+            let count = if words.len() > 2 { arg(2)? } else { 8 };
+
+            for row in 0..(count as usize).div_ceil(8) {
+                let start = address.wrapping_add(row as u16 * 8);
+                let values: Vec<String> = (0..8.min(count as usize - row * 8))
+                    .map(|d| {
+                        format!("{:5}", session.read(start.wrapping_add(d as u16)))
+                    })
+                    .collect();
+                println!("{:5}: {}", start, values.join(" "));
+            }
+        },
+        "w" | "write" => {
+            let address = arg(1)?;
+            let value = arg(2)?;
+            session.write(address, value);
+        },
+        "l" | "list" => {
+            let address = if words.len() > 1 { arg(1)? } else { session.pc() };
+            show_listing(session, address, 8);
+        },
+        "h" | "help" => println!("{}", HELP),
+        "q" | "quit" => return Ok(false),
+        x => return Err(format!("unknown command `{}`, try `help`", x)),
+    }
+
+    Ok(true)
+}
+
+/// Runs the interactive debugger on stdin until `quit` or end of input.
+pub fn repl(mut session: Session) {
+    let stdin = stdin();
+    let mut lines = stdin.lock().lines();
+    let mut last = String::new();
+
+    show_current(&mut session);
+
+    loop {
+        print!("(rdb) ");
+        let _ = stdout().flush();
+
+        let mut line = match lines.next() {
+            Some(Ok(x)) => x,
+            _ => break,
+        };
+
+        // an empty line repeats the previous command
+        if line.trim().is_empty() {
+            line = last.clone();
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+
+        match command(&mut session, &words) {
+            Ok(true) => {},
+            Ok(false) => break,
+            Err(x) => println!("error: {}", x),
+        }
+
+        let _ = stdout().flush();
+        last = line;
+    }
+}
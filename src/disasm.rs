@@ -0,0 +1,82 @@
+use RISC_16_bit::isa::Instruction;
+
+/// One decoded instruction, or a single word that does not decode and is
+/// shown as data.
+pub struct DisasmLine {
+    pub address: u16,
+    pub words: Vec<u16>,
+    pub text: String,
+    pub data: bool,
+}
+
+pub fn disassemble(program: &[u16]) -> Vec<DisasmLine> {
+    disassemble_from(program, 0)
+}
+
+/// Disassembles `program` as if it were loaded at `origin`.
+pub fn disassemble_from(program: &[u16], origin: u16) -> Vec<DisasmLine> {
+    let mut lines = vec![];
+    let mut i = 0;
+
+    while i < program.len() {
+        let (words, text, data) = match Instruction::decode(&program[i..]) {
+            Some(x) => (x.encode(), x.to_string(), false),
+            None => (vec![program[i]], format!(".word 0x{:04X}", program[i]), true),
+        };
+
+        i += words.len();
+
+        lines.push(DisasmLine {
+            address: origin.wrapping_add((i - words.len()) as u16),
+            words,
+            text,
+            data,
+        });
+    }
+
+    lines
+}
+
+impl DisasmLine {
+    pub fn render(&self) -> String {
+        let words: Vec<String> = self.words.iter()
+            .map(|d| {
+                format!("{:04X}", d)
+            })
+            .collect();
+
+        format!("{:04X}:  {:<15} {}{}", self.address, words.join(" "), self.text, if self.data { "  ; data" } else { "" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile_raw;
+
+    #[test]
+    fn decodes_instructions_and_data() {
+        let lines = disassemble(&[3, 2, 0x41, 5, 2, 7, 0xFF, 2, 9, 0]);
+        let texts: Vec<&str> = lines.iter().map(|x| &x.text[..]).collect();
+        assert_eq!(texts, ["SET a 65", "ADD a f", ".word 0x00FF", ".word 0x0002", ".word 0x0009", "HLT"]);
+
+        assert_eq!(lines[0].render(), "0000:  0003 0002 0041  SET a 65");
+        assert_eq!(lines[2].render(), "0006:  00FF            .word 0x00FF  ; data");
+        assert_eq!(lines.iter().map(|x| x.address).collect::<Vec<_>>(), [0, 3, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn bad_registers_and_short_programs_are_data() {
+        let lines = disassemble(&[4, 2, 8, 11, 2]);
+        assert!(lines.iter().all(|x| x.data));
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn reassembles() {
+        let source = "start: SET a 0x1234\nPNT out count\nloop: SUB b c\nJNZ a f\nLST d e\nHLT\n";
+        let program = compile_raw(source, "test.rasm").ok().unwrap();
+        let text: Vec<String> = disassemble(&program).into_iter().map(|x| x.text).collect();
+        assert_eq!(compile_raw(&text.join("\n"), "test.rasm").ok(), Some(program));
+    }
+}
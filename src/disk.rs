@@ -0,0 +1,289 @@
+use modVM::*;
+use modVM::Query::*;
+use modVM::Response::*;
+use crate::bus::Inspect;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use crate::bus::Dma;
+use crate::interrupt::InterruptLine;
+
+/// How many words a sector holds.
+pub const SECTOR_WORDS: u16 = 256;
+
+/// The command that copies the selected sector into the buffer.
+pub const READ: u16 = 1;
+/// The command that copies the buffer into the selected sector.
+pub const WRITE: u16 = 2;
+
+/// The last command finished, or none has been given.
+pub const READY: u16 = 0;
+/// A command is still running.
+pub const BUSY: u16 = 1;
+/// The last command was not recognised or the image could not be accessed.
+pub const ERROR: u16 = 2;
+
+/// A disk controller backed by an image file on the host, which it reads and
+/// writes a sector of `SECTOR_WORDS` words at a time. Sector `n` is bytes
+/// `512n..512n + 512` of the image, holding its words big-endian. Sectors past
+/// the end of the image read as zeros, and writing to one grows the image.
+///
+/// | Offset | Word                                                          |
+/// |--------|---------------------------------------------------------------|
+/// | 0      | Sector number.                                                |
+/// | 1      | Buffer address, where in memory the sector is copied to/from. |
+/// | 2      | Command. Saving `READ` or `WRITE` starts a transfer.          |
+/// | 3      | Status: `READY`, `BUSY` or `ERROR`.                           |
+///
+/// Sectors are copied through the bus's `Dma` sender, so a transfer finishes
+/// before the program's next access to memory, but programs should still wait
+/// for the status to leave `BUSY`. When connected to an interrupt line, the
+/// controller also raises it each time a command finishes.
+///
+/// Only the program's saves start commands. Loading an image or resuming a
+/// snapshot leaves the registers alone, so neither runs a command again.
+pub struct Disk {
+    image: File,
+    dma: Sender<Dma>,
+    sector: u16,
+    buffer: u16,
+    command: u16,
+    status: u16,
+    /// The sector being written, and where its words will arrive from.
+    writing: Option<(u16, Receiver<Vec<u16>>)>,
+    line: Option<InterruptLine>,
+}
+
+impl Disk {
+    /// Opens the image at `path`, creating it if it does not exist.
+    pub fn open(path: &Path, dma: Sender<Dma>) -> io::Result<Disk> {
+        let image = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+
+        Ok(Disk {
+            image,
+            dma,
+            sector: 0,
+            buffer: 0,
+            command: 0,
+            status: READY,
+            writing: None,
+            line: None,
+        })
+    }
+
+    pub fn connect(&mut self, line: InterruptLine) {
+        self.line = Some(line);
+    }
+
+    fn read_sector(&mut self, sector: u16) -> io::Result<Vec<u16>> {
+        let mut bytes = vec![0; 2 * SECTOR_WORDS as usize];
+        self.image.seek(SeekFrom::Start(sector as u64 * bytes.len() as u64))?;
+
+        // the image may end part way through the sector
+        let mut filled = 0;
+        while filled < bytes.len() {
+            match self.image.read(&mut bytes[filled..])? {
+                0 => break,
+                x => filled += x,
+            }
+        }
+
+        Ok(bytes.chunks(2).map(|d| u16::from_be_bytes([d[0], d[1]])).collect())
+    }
+
+    fn write_sector(&mut self, sector: u16, words: &[u16]) -> io::Result<()> {
+        let bytes: Vec<u8> = words.iter().flat_map(|d| d.to_be_bytes().to_vec()).collect();
+        self.image.seek(SeekFrom::Start(sector as u64 * 2 * SECTOR_WORDS as u64))?;
+        self.image.write_all(&bytes)?;
+        self.image.flush()
+    }
+
+    fn finish(&mut self, result: io::Result<()>) {
+        self.status = if result.is_ok() { READY } else { ERROR };
+
+        if let Some(x) = &self.line {
+            x.raise();
+        }
+    }
+
+    fn start(&mut self, command: u16) {
+        self.command = command;
+
+        match command {
+            READ => {
+                let result = self.read_sector(self.sector).and_then(|words| {
+                    self.dma.send(Dma::Save { address: self.buffer, words })
+                        .map_err(|_| io::Error::other("the bus is gone"))
+                });
+                self.finish(result);
+            },
+            WRITE => {
+                let (reply, words) = channel();
+                let request = Dma::Load { address: self.buffer, len: SECTOR_WORDS, reply };
+
+                if self.dma.send(request).is_ok() {
+                    self.writing = Some((self.sector, words));
+                    self.status = BUSY;
+                } else {
+                    self.finish(Err(io::Error::other("the bus is gone")));
+                }
+            },
+            _ => self.finish(Err(io::Error::other("unknown command"))),
+        }
+    }
+}
+
+impl Peripheral<u16> for Disk {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            model: String::from("Disk Controller v.0.0.0"),
+        }
+    }
+
+    fn handle(&mut self, incoming: Query<u16>) -> Result<Response<u16>, u16> {
+        Ok(match incoming {
+            LoadRequest(x) => match self.peek(x) {
+                Some(y) => Data(y),
+                None => Fail(0),
+            },
+            SaveRequest(x, 0) => {
+                self.sector = x;
+                Good
+            },
+            SaveRequest(x, 1) => {
+                self.buffer = x;
+                Good
+            },
+            SaveRequest(x, 2) => {
+                if self.status != BUSY {
+                    self.start(x);
+                }
+                Good
+            },
+            SaveRequest(_, 3) => Good,
+            _ => Fail(0),
+        })
+    }
+
+    fn cycle(&mut self) -> Result<(), u16> {
+        let words = match &self.writing {
+            Some((_, x)) => match x.try_recv() {
+                Ok(x) => Ok(x),
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => Err(io::Error::other("the bus is gone")),
+            },
+            None => return Ok(()),
+        };
+
+        if let Some((sector, _)) = self.writing.take() {
+            let result = words.and_then(|d| self.write_sector(sector, &d));
+            self.finish(result);
+        }
+        Ok(())
+    }
+}
+
+impl Inspect for Disk {
+    fn peek(&self, offset: u16) -> Option<u16> {
+        match offset {
+            0 => Some(self.sector),
+            1 => Some(self.buffer),
+            2 => Some(self.command),
+            3 => Some(self.status),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::{read, remove_file};
+    use std::path::PathBuf;
+
+    fn image(name: &str) -> PathBuf {
+        let path = temp_dir().join(format!("risc-disk-{}-{}.img", std::process::id(), name));
+        let _ = remove_file(&path);
+        path
+    }
+
+    fn save(disk: &mut Disk, offset: u16, value: u16) {
+        assert!(matches!(disk.handle(SaveRequest(value, offset)), Ok(Good)));
+    }
+
+    fn load(disk: &mut Disk, offset: u16) -> u16 {
+        match disk.handle(LoadRequest(offset)) {
+            Ok(Data(x)) => x,
+            _ => panic!("offset {} failed to load", offset),
+        }
+    }
+
+    #[test]
+    fn writes_then_reads_a_sector() {
+        let path = image("round-trip");
+        let (dma, transfers) = channel();
+        let mut disk = Disk::open(&path, dma).unwrap();
+
+        save(&mut disk, 0, 2);
+        save(&mut disk, 1, 0x400);
+        save(&mut disk, 2, WRITE);
+        assert_eq!(load(&mut disk, 3), BUSY);
+
+        // answer the load as the bus would
+        match transfers.try_recv() {
+            Ok(Dma::Load { address: 0x400, len: SECTOR_WORDS, reply }) => {
+                reply.send((0..SECTOR_WORDS).collect()).unwrap();
+            },
+            _ => panic!("expected a load of the buffer"),
+        }
+        disk.cycle().unwrap();
+        assert_eq!(load(&mut disk, 3), READY);
+
+        // the sector lands big-endian at its place in the image
+        let bytes = read(&path).unwrap();
+        assert_eq!(bytes.len(), 3 * 512);
+        assert!(bytes[..1024].iter().all(|d| *d == 0));
+        assert_eq!(&bytes[1024..1028], &[0, 0, 0, 1]);
+
+        save(&mut disk, 1, 0x800);
+        save(&mut disk, 2, READ);
+        assert_eq!(load(&mut disk, 3), READY);
+        match transfers.try_recv() {
+            Ok(Dma::Save { address: 0x800, words }) => {
+                assert_eq!(words, (0..SECTOR_WORDS).collect::<Vec<u16>>());
+            },
+            _ => panic!("expected a save into the buffer"),
+        }
+        let _ = remove_file(&path);
+    }
+
+    #[test]
+    fn sectors_past_the_end_read_as_zeros() {
+        let path = image("past-the-end");
+        let (dma, transfers) = channel();
+        let mut disk = Disk::open(&path, dma).unwrap();
+
+        save(&mut disk, 0, 9);
+        save(&mut disk, 2, READ);
+        assert_eq!(load(&mut disk, 3), READY);
+        match transfers.try_recv() {
+            Ok(Dma::Save { address: 0, words }) => assert_eq!(words, vec![0; SECTOR_WORDS as usize]),
+            _ => panic!("expected a save into the buffer"),
+        }
+        let _ = remove_file(&path);
+    }
+
+    #[test]
+    fn unknown_commands_fail() {
+        let path = image("unknown");
+        let (dma, _transfers) = channel();
+        let mut disk = Disk::open(&path, dma).unwrap();
+
+        save(&mut disk, 2, 7);
+        assert_eq!(load(&mut disk, 3), ERROR);
+        assert!(matches!(disk.handle(LoadRequest(4)), Ok(Fail(_))));
+        let _ = remove_file(&path);
+    }
+}
@@ -0,0 +1,443 @@
+use std::collections::HashMap;
+use crate::compiler::{unescape, ErrorKind};
+
+/// An error inside an expression. `offset` and `len` are in characters and
+/// relative to the start of the expression text.
+pub struct ExprError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub offset: usize,
+    pub len: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Lexeme<'a> {
+    Number(i64),
+    Name(&'a str),
+    Op(&'static str),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Item<'a> {
+    lexeme: Lexeme<'a>,
+    offset: usize,
+    len: usize,
+}
+
+// longest first, so that `<<` is not read as two `<`
+const OPERATORS: [&str; 23] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>",
+    "|", "^", "&", "<", ">", "+", "-", "*", "/", "!", "~", "(", ")", "[", "]",
+];
+
+// lowest to highest precedence
+const LEVELS: [&[&str]; 9] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!=", "<=", ">=", "<", ">"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/"],
+];
+
+/// How far from 0 any value in an expression may get, whatever its sign.
+const LIMIT: u64 = u32::MAX as u64;
+
+fn error(kind: ErrorKind, message: String, offset: usize, len: usize) -> ExprError {
+    ExprError {
+        kind,
+        message,
+        offset,
+        len,
+    }
+}
+
+fn lex(text: &str) -> Result<Vec<Item<'_>>, ExprError> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut items = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (byte, d) = chars[i];
+
+        if d.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+
+        let lexeme = if d.is_ascii_digit() {
+            while i < chars.len() && (chars[i].1.is_ascii_alphanumeric() || chars[i].1 == '_') {
+                i += 1;
+            }
+            let end = chars.get(i).map(|d| d.0).unwrap_or(text.len());
+            let literal = &text[byte..end];
+
+            match parse_number(literal) {
+                Some(x) => Lexeme::Number(x),
+                None => return Err(error(ErrorKind::InvalidNumber, format!("invalid number `{}`", literal), start, i - start)),
+            }
+        } else if d.is_ascii_alphabetic() || d == '_' || d == '.' {
+            while i < chars.len() && (chars[i].1.is_ascii_alphanumeric() || chars[i].1 == '_' || chars[i].1 == '.') {
+                i += 1;
+            }
+            let end = chars.get(i).map(|d| d.0).unwrap_or(text.len());
+            Lexeme::Name(&text[byte..end])
+        } else if d == '\'' {
+            i += 1;
+            while i < chars.len() && chars[i].1 != '\'' {
+                if chars[i].1 == '\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err(error(ErrorKind::InvalidNumber, String::from("unterminated character literal"), start, i - start));
+            }
+            i += 1;
+
+            let body = &text[byte + 1..chars[i - 1].0];
+            match unescape(body) {
+                Ok(ref x) if x.len() == 1 => Lexeme::Number(x[0] as i64),
+                Ok(_) => return Err(error(ErrorKind::InvalidNumber, String::from("character literals must hold exactly one character"), start, i - start)),
+                Err(x) => return Err(error(ErrorKind::InvalidNumber, x, start, i - start)),
+            }
+        } else {
+            let rest = &text[byte..];
+            match OPERATORS.iter().find(|x| rest.starts_with(*x)) {
+                Some(x) => {
+                    i += x.len();
+                    Lexeme::Op(x)
+                },
+                None => return Err(error(ErrorKind::InvalidExpression, format!("unexpected character `{}`", d), start, 1)),
+            }
+        };
+
+        items.push(Item {
+            lexeme,
+            offset: start,
+            len: i - start,
+        });
+    }
+
+    Ok(items)
+}
+
+/// Parses a decimal, `0x` hexadecimal, `0o` octal or `0b` binary literal.
+pub fn parse_number(s: &str) -> Option<i64> {
+    let s = s.replace('_', "");
+
+    let (digits, radix) = if s.starts_with("0x") || s.starts_with("0X") {
+        (&s[2..], 16)
+    } else if s.starts_with("0b") || s.starts_with("0B") {
+        (&s[2..], 2)
+    } else if s.starts_with("0o") || s.starts_with("0O") {
+        (&s[2..], 8)
+    } else {
+        (&s[..], 10)
+    };
+
+    i64::from_str_radix(digits, radix).ok()
+        .filter(|_| !digits.is_empty() && !digits.starts_with(['+', '-']))
+}
+
+/// A parsed expression. `offset` and `len` locate, in characters, the token
+/// it is reported against: its operator, name or literal.
+pub struct Expr {
+    pub node: Node,
+    pub offset: usize,
+    pub len: usize,
+}
+
+pub enum Node {
+    Number(i64),
+    Name(String),
+    /// The word of memory at an address, written `[address]`.
+    Memory(Box<Expr>),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+/// What names and memory mean while an expression is evaluated.
+pub trait Scope {
+    /// The value of `name`, or `None` if it is not defined.
+    fn name(&mut self, name: &str) -> Option<i64>;
+
+    /// The word at `address`, or `None` if memory cannot be read here.
+    fn memory(&mut self, _address: u16) -> Option<i64> {
+        None
+    }
+}
+
+/// The assembler's scope: names are symbols and there is no memory.
+impl Scope for &HashMap<&str, u16> {
+    fn name(&mut self, name: &str) -> Option<i64> {
+        self.get(name).map(|x| *x as i64)
+    }
+}
+
+impl Expr {
+    fn at(&self, kind: ErrorKind, message: String) -> ExprError {
+        error(kind, message, self.offset, self.len)
+    }
+
+    /// Passes on the result of applying this expression's operator, unless it
+    /// failed or left the range values are kept to.
+    fn bounded(&self, value: Option<i64>, op: &str) -> Result<i64, ExprError> {
+        match value {
+            Some(x) if x.unsigned_abs() <= LIMIT => Ok(x),
+            _ => Err(self.at(ErrorKind::OutOfRange, format!("`{}` overflows", op))),
+        }
+    }
+
+    /// Works out the value of the expression in `scope`. Comparisons, `!`,
+    /// `&&` and `||` give 1 or 0, and `&&` and `||` only evaluate their right
+    /// hand side when they need to.
+    pub fn evaluate(&self, scope: &mut dyn Scope) -> Result<i64, ExprError> {
+        match &self.node {
+            Node::Number(x) => Ok(*x),
+            Node::Name(x) => match scope.name(x) {
+                Some(y) => Ok(y),
+                None => Err(self.at(ErrorKind::UndefinedSymbol, format!("use of undefined symbol `{}`", x))),
+            },
+            Node::Memory(x) => {
+                let address = x.evaluate(scope)? as u16;
+                match scope.memory(address) {
+                    Some(y) => Ok(y),
+                    None => Err(self.at(ErrorKind::InvalidExpression, String::from("memory cannot be read here"))),
+                }
+            },
+            Node::Unary(op, x) => {
+                let x = x.evaluate(scope)?;
+                let value = match *op {
+                    "-" => x.checked_neg(),
+                    "~" => Some(!x),
+                    "!" => Some((x == 0) as i64),
+                    _ => Some(x),
+                };
+                self.bounded(value, op)
+            },
+            Node::Binary(op, x, y) => {
+                let lhs = x.evaluate(scope)?;
+                match (*op, lhs != 0) {
+                    ("&&", false) => return Ok(0),
+                    ("||", true) => return Ok(1),
+                    _ => {},
+                }
+                let rhs = y.evaluate(scope)?;
+
+                let value = match *op {
+                    "||" | "&&" => Some((rhs != 0) as i64),
+                    "|" => Some(lhs | rhs),
+                    "^" => Some(lhs ^ rhs),
+                    "&" => Some(lhs & rhs),
+                    "==" => Some((lhs == rhs) as i64),
+                    "!=" => Some((lhs != rhs) as i64),
+                    "<=" => Some((lhs <= rhs) as i64),
+                    ">=" => Some((lhs >= rhs) as i64),
+                    "<" => Some((lhs < rhs) as i64),
+                    ">" => Some((lhs > rhs) as i64),
+                    "<<" => if (0..32).contains(&rhs) { lhs.checked_shl(rhs as u32) } else { None },
+                    ">>" => if (0..32).contains(&rhs) { lhs.checked_shr(rhs as u32) } else { None },
+                    "+" => lhs.checked_add(rhs),
+                    "-" => lhs.checked_sub(rhs),
+                    "*" => lhs.checked_mul(rhs),
+                    _ => {
+                        if rhs == 0 {
+                            return Err(self.at(ErrorKind::InvalidExpression, String::from("division by zero")));
+                        }
+                        lhs.checked_div(rhs)
+                    },
+                };
+                self.bounded(value, op)
+            },
+        }
+    }
+}
+
+struct Parser<'a> {
+    items: Vec<Item<'a>>,
+    pos: usize,
+    end: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek_op(&self, ops: &[&'static str]) -> Option<(&'static str, Item<'a>)> {
+        match self.items.get(self.pos) {
+            Some(x) => match x.lexeme {
+                Lexeme::Op(y) if ops.contains(&y) => Some((y, *x)),
+                _ => None,
+            },
+            None => None,
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, ExprError> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+
+        while let Some((op, item)) = self.peek_op(LEVELS[level]) {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+
+            lhs = Expr {
+                node: Node::Binary(op, Box::new(lhs), Box::new(rhs)),
+                offset: item.offset,
+                len: item.len,
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ExprError> {
+        match self.peek_op(&["-", "+", "~", "!"]) {
+            Some((op, item)) => {
+                self.pos += 1;
+                Ok(Expr {
+                    node: Node::Unary(op, Box::new(self.unary()?)),
+                    offset: item.offset,
+                    len: item.len,
+                })
+            },
+            None => self.primary(),
+        }
+    }
+
+    /// Parses the expression inside a pair of brackets opened by `item`.
+    fn enclosed(&mut self, item: Item, close: &'static str) -> Result<Expr, ExprError> {
+        let inner = self.binary(0)?;
+        match self.peek_op(&[close]) {
+            Some(_) => {
+                self.pos += 1;
+                Ok(inner)
+            },
+            None => Err(error(ErrorKind::InvalidExpression, format!("expected `{}`", close), item.offset, item.len)),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ExprError> {
+        let item = match self.items.get(self.pos) {
+            Some(x) => *x,
+            None => return Err(error(ErrorKind::InvalidExpression, String::from("expected a value"), self.end, 1)),
+        };
+        self.pos += 1;
+
+        let node = match item.lexeme {
+            Lexeme::Number(x) if x.unsigned_abs() <= LIMIT => Node::Number(x),
+            Lexeme::Number(_) => return Err(error(ErrorKind::OutOfRange, String::from("number is too large"), item.offset, item.len)),
+            Lexeme::Name(x) => Node::Name(x.to_string()),
+            Lexeme::Op("(") => return self.enclosed(item, ")"),
+            Lexeme::Op("[") => Node::Memory(Box::new(self.enclosed(item, "]")?)),
+            _ => return Err(error(ErrorKind::InvalidExpression, String::from("expected a value"), item.offset, item.len)),
+        };
+
+        Ok(Expr {
+            node,
+            offset: item.offset,
+            len: item.len,
+        })
+    }
+}
+
+/// Parses an expression. Values are decimal, `0x`, `0o` and `0b` literals,
+/// character literals, names and memory words written `[address]`. The unary
+/// operators are `- + ~ !`, and the binary operators, in decreasing
+/// precedence, `* /`, `+ -`, `<< >>`, comparisons, `&`, `^`, `|`, `&&` and
+/// `||`, with parentheses for grouping.
+pub fn parse(text: &str) -> Result<Expr, ExprError> {
+    let mut parser = Parser {
+        items: lex(text)?,
+        pos: 0,
+        end: text.chars().count(),
+    };
+
+    let expr = parser.binary(0)?;
+
+    match parser.items.get(parser.pos) {
+        Some(x) => Err(error(ErrorKind::InvalidExpression, String::from("unexpected token in expression"), x.offset, x.len)),
+        None => Ok(expr),
+    }
+}
+
+/// Evaluates a constant expression over `symbols`, in which memory cannot be read.
+pub fn evaluate(text: &str, symbols: &HashMap<&str, u16>) -> Result<i64, ExprError> {
+    let mut scope = symbols;
+    parse(text)?.evaluate(&mut scope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(text: &str) -> Result<i64, ErrorKind> {
+        let symbols: HashMap<&str, u16> = [("msg", 0x20), ("len", 5)].iter().cloned().collect();
+        evaluate(text, &symbols).map_err(|x| x.kind)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(value("1 + 2 * 3"), Ok(7));
+        assert_eq!(value("(1 + 2) * 3"), Ok(9));
+        assert_eq!(value("1 << 2 + 1"), Ok(8));
+        assert_eq!(value("0xF0 | 0x0F & 0x3"), Ok(0xF3));
+        assert_eq!(value("10 - 4 - 3"), Ok(3));
+        assert_eq!(value("-msg + len"), Ok(-0x1B));
+        assert_eq!(value("~0 & 0xFF"), Ok(0xFF));
+        assert_eq!(value("6 ^ 3 & 1 | 8"), Ok(15));
+        assert_eq!(value("len + 1 == 6 && msg > len || 0"), Ok(1));
+        assert_eq!(value("!len + !0"), Ok(1));
+    }
+
+    #[test]
+    fn logic_short_circuits() {
+        assert_eq!(value("0 && 1 / 0"), Ok(0));
+        assert_eq!(value("len || nope"), Ok(1));
+        assert_eq!(value("1 && 2"), Ok(1));
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(value("0x1_0"), Ok(16));
+        assert_eq!(value("0b101 + 0o17"), Ok(20));
+        assert_eq!(value("'A'"), Ok(65));
+        assert_eq!(value("'\\n'"), Ok(10));
+        assert_eq!(value("'\\x7f'"), Ok(0x7F));
+        assert_eq!(value("'\\''"), Ok(39));
+        assert_eq!(value("'ab'"), Err(ErrorKind::InvalidNumber));
+        assert_eq!(value("'\\q'"), Err(ErrorKind::InvalidNumber));
+        assert_eq!(value("0x"), Err(ErrorKind::InvalidNumber));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(value("0xFFFF * 0xFFFF * 0xFFFF"), Err(ErrorKind::OutOfRange));
+        assert_eq!(value("1 << 40"), Err(ErrorKind::OutOfRange));
+        assert_eq!(value("-~9223372036854775807"), Err(ErrorKind::OutOfRange));
+        assert_eq!(value("~9223372036854775807 | 0"), Err(ErrorKind::OutOfRange));
+        assert_eq!(value("0x1_0000_0000"), Err(ErrorKind::OutOfRange));
+        assert_eq!(value("~0xFFFF_FFFF"), Err(ErrorKind::OutOfRange));
+        assert_eq!(value("-0xFFFF_FFFF"), Ok(-0xFFFF_FFFF));
+        assert_eq!(value("1 / 0"), Err(ErrorKind::InvalidExpression));
+        assert_eq!(value("(1 + 2"), Err(ErrorKind::InvalidExpression));
+        assert_eq!(value("1 2"), Err(ErrorKind::InvalidExpression));
+        assert_eq!(value("nope"), Err(ErrorKind::UndefinedSymbol));
+        assert_eq!(value("[msg]"), Err(ErrorKind::InvalidExpression));
+        assert_eq!(value("[msg"), Err(ErrorKind::InvalidExpression));
+    }
+
+    #[test]
+    fn numbers_have_no_sign() {
+        assert_eq!(parse_number("42"), Some(42));
+        assert_eq!(parse_number("-1"), None);
+        assert_eq!(parse_number("+1"), None);
+        assert_eq!(parse_number("0x-1"), None);
+    }
+}
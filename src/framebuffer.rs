@@ -0,0 +1,213 @@
+use modVM::*;
+use modVM::Query::*;
+use modVM::Response::*;
+use crate::bus::Inspect;
+use std::fs::{create_dir_all, write};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+pub const COLUMNS: usize = 80;
+pub const ROWS: usize = 25;
+
+/// How many words the framebuffer takes up.
+pub const CELLS: usize = COLUMNS * ROWS;
+
+/// Where frames are drawn.
+#[derive(Debug, Clone)]
+pub enum Output {
+    /// The host terminal, redrawn in place with ANSI escapes.
+    Terminal,
+    /// Numbered text files in a directory, `frame-00000.txt` onwards, holding
+    /// just the characters of each frame.
+    Dump(PathBuf),
+}
+
+/// The ANSI colour for each of the 8 colours in an attribute, which are
+/// numbered as on CGA: black, blue, green, cyan, red, magenta, brown, grey.
+const ANSI: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+struct Screen {
+    cells: Vec<u16>,
+    output: Output,
+    /// Whether the cells have changed since they were last drawn.
+    dirty: bool,
+    frames: u32,
+}
+
+fn character(cell: u16) -> char {
+    match cell as u8 {
+        x @ 0x20..=0x7E => x as char,
+        _ => ' ',
+    }
+}
+
+/// The escape selecting the colours in `attribute`: the low nibble is the
+/// foreground and the high nibble the background, where bit 3 of each makes it
+/// bright. 0 selects the terminal's own colours.
+fn colours(attribute: u8) -> String {
+    if attribute == 0 {
+        return String::from("\x1b[0m");
+    }
+
+    let colour = |d: u8, base: u8| {
+        let bright = if d & 8 != 0 { 60 } else { 0 };
+        base + bright + ANSI[(d & 7) as usize]
+    };
+    format!("\x1b[0;{};{}m", colour(attribute & 0xF, 30), colour(attribute >> 4, 40))
+}
+
+impl Screen {
+    fn draw(&mut self) -> io::Result<()> {
+        match &self.output {
+            Output::Terminal => {
+                let mut text = String::from(if self.frames == 0 { "\x1b[2J\x1b[H" } else { "\x1b[H" });
+                for row in self.cells.chunks(COLUMNS) {
+                    let mut attribute = None;
+                    for cell in row {
+                        let x = (cell >> 8) as u8;
+                        if attribute != Some(x) {
+                            text.push_str(&colours(x));
+                            attribute = Some(x);
+                        }
+                        text.push(character(*cell));
+                    }
+                    text.push_str("\x1b[0m\r\n");
+                }
+
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                stdout.write_all(text.as_bytes())?;
+                stdout.flush()?;
+            },
+            Output::Dump(directory) => {
+                let mut text = String::new();
+                for row in self.cells.chunks(COLUMNS) {
+                    text.extend(row.iter().map(|d| character(*d)));
+                    text.push('\n');
+                }
+                write(directory.join(format!("frame-{:05}.txt", self.frames)), text)?;
+            },
+        }
+
+        self.frames += 1;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// A handle on what a `Framebuffer` shows, which outlives the machine it is
+/// attached to so that the last frame can be drawn once the machine stops.
+#[derive(Clone)]
+pub struct Display(Arc<Mutex<Screen>>);
+
+impl Display {
+    /// Draws the frame if it has changed since it was last drawn.
+    pub fn refresh(&self) -> io::Result<()> {
+        let mut screen = self.0.lock().unwrap();
+        if screen.dirty {
+            screen.draw()?;
+        }
+        Ok(())
+    }
+}
+
+/// A `COLUMNS` by `ROWS` text screen, one word per character cell, row by row.
+/// The low byte of a cell is its character and the high byte its attribute, as
+/// described by `colours`.
+///
+/// Every `refresh` calls to `Peripheral::cycle` the screen is drawn to its
+/// `Output`, if anything has changed. Like the timer, under `run` that follows
+/// the host's clock rather than the program.
+pub struct Framebuffer {
+    display: Display,
+    refresh: u32,
+    remaining: u32,
+}
+
+impl Framebuffer {
+    /// A blank screen, creating the directory frames are dumped to if need be.
+    pub fn new(output: Output, refresh: u32) -> io::Result<Framebuffer> {
+        if let Output::Dump(x) = &output {
+            create_dir_all(x)?;
+        }
+
+        let screen = Screen {
+            cells: vec![0; CELLS],
+            output,
+            dirty: false,
+            frames: 0,
+        };
+
+        Ok(Framebuffer {
+            display: Display(Arc::new(Mutex::new(screen))),
+            refresh: refresh.max(1),
+            remaining: refresh.max(1),
+        })
+    }
+
+    pub fn display(&self) -> Display {
+        self.display.clone()
+    }
+}
+
+impl Peripheral<u16> for Framebuffer {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            model: String::from("Text Framebuffer v.0.0.0"),
+        }
+    }
+
+    fn handle(&mut self, incoming: Query<u16>) -> Result<Response<u16>, u16> {
+        let mut screen = self.display.0.lock().unwrap();
+
+        Ok(match incoming {
+            LoadRequest(x) => match screen.cells.get(x as usize) {
+                Some(x) => Data(*x),
+                None => Fail(0),
+            },
+            SaveRequest(x, y) => match screen.cells.get_mut(y as usize) {
+                Some(cell) => {
+                    if *cell != x {
+                        *cell = x;
+                        screen.dirty = true;
+                    }
+                    Good
+                },
+                None => Fail(0),
+            },
+        })
+    }
+
+    fn cycle(&mut self) -> Result<(), u16> {
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            self.remaining = self.refresh;
+            // a frame that cannot be drawn is skipped rather than stopping the machine
+            let _ = self.display.refresh();
+        }
+        Ok(())
+    }
+}
+
+impl Inspect for Framebuffer {
+    fn peek(&self, offset: u16) -> Option<u16> {
+        self.display.0.lock().unwrap().cells.get(offset as usize).cloned()
+    }
+
+    fn poke(&mut self, offset: u16, value: u16) -> bool {
+        let mut screen = self.display.0.lock().unwrap();
+        match screen.cells.get_mut(offset as usize) {
+            Some(x) => {
+                *x = value;
+                screen.dirty = true;
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn is_memory(&self) -> bool {
+        true
+    }
+}
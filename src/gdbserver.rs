@@ -0,0 +1,413 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use RISC_16_bit::*;
+use RISC_16_bit::watch::{WatchKind, Watchpoint};
+use crate::debugger::{Session, Stop};
+
+const TARGET_XML: &str = include_str!("target.xml");
+
+/// How many instructions `continue` runs between checks for an interrupt.
+const POLL_INTERVAL: usize = 1024;
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |acc, d| acc.wrapping_add(*d))
+}
+
+fn hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len()).step_by(2)
+        .map(|d| {
+            text.get(d..d + 2).and_then(|x| u8::from_str_radix(x, 16).ok())
+        })
+        .collect()
+}
+
+/// Escapes the characters that may not appear in a packet's body.
+fn escape(data: &str) -> String {
+    let mut escaped = String::new();
+    for c in data.chars() {
+        match c {
+            '#' | '$' | '}' | '*' => {
+                escaped.push('}');
+                escaped.push((c as u8 ^ 0x20) as char);
+            },
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// How many bytes register `r` takes up. `count` holds a byte address, which
+/// needs 17 bits, so it is sent as 32.
+fn register_len(r: usize) -> usize {
+    if r == 1 { 4 } else { 2 }
+}
+
+/// The bytes `g` sends for all eight registers.
+const REGISTERS_LEN: usize = 18;
+
+/// Formats register `r`, turning `count` into the byte address of the word it
+/// points at so that it agrees with memory and breakpoints.
+fn encode_register(r: usize, value: u16) -> String {
+    if r == 1 {
+        format!("{:08x}", value as u32 * 2)
+    } else {
+        format!("{:04x}", value)
+    }
+}
+
+fn decode_register(r: usize, bytes: &[u8]) -> Option<u16> {
+    match bytes {
+        [high, low] if r != 1 => Some(u16::from_be_bytes([*high, *low])),
+        [a, b, c, d] if r == 1 => {
+            let address = u32::from_be_bytes([*a, *b, *c, *d]);
+            if address < 0x20000 { Some((address / 2) as u16) } else { None }
+        },
+        _ => None,
+    }
+}
+
+/// Splits `addr,len` into its two numbers.
+fn address_length(text: &str) -> Option<(u32, u32)> {
+    let mut parts = text.splitn(2, ',');
+    Some((hex(parts.next()?)?, hex(parts.next()?)?))
+}
+
+fn stop_reply(stop: &Stop) -> String {
+    match stop {
+        Stop::Breakpoint(_) | Stop::Predicate(_) => String::from("S05"),
+        Stop::StartOfHistory => String::from("T05replaylog:begin;"),
+        Stop::Watch(hits) => {
+            let kind = match hits[0].watchpoint.kind {
+                WatchKind::Write => "watch",
+                WatchKind::Read => "rwatch",
+                WatchKind::Access => "awatch",
+            };
+            format!("T05{}:{:x};", kind, hits[0].address as u32 * 2)
+        },
+        Stop::Outcome(Outcome::Halted { .. }) => String::from("W00"),
+        Stop::Outcome(Outcome::Faulted(Fault::IllegalOpcode { .. })) |
+        Stop::Outcome(Outcome::Faulted(Fault::BadRegister { .. })) => String::from("S04"),
+        Stop::Outcome(Outcome::Faulted(_)) => String::from("S0b"),
+    }
+}
+
+struct Server {
+    session: Session,
+    stream: TcpStream,
+    /// The reply to `?`.
+    last: String,
+}
+
+impl Server {
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        let mut buf = [0];
+        match self.stream.read(&mut buf)? {
+            0 => Ok(None),
+            _ => Ok(Some(buf[0])),
+        }
+    }
+
+    /// Reads the next packet, acknowledging it, or `None` once GDB disconnects.
+    fn packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // acks, and interrupts arriving while the machine is stopped, are skipped
+            match self.byte()? {
+                None => return Ok(None),
+                Some(b'$') => {},
+                Some(_) => continue,
+            }
+
+            let mut data = vec![];
+            loop {
+                match self.byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(x) => data.push(x),
+                }
+            }
+
+            let mut sum = [0; 2];
+            self.stream.read_exact(&mut sum)?;
+            let expected = std::str::from_utf8(&sum).ok().and_then(hex);
+
+            if expected == Some(checksum(&data) as u32) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        write!(self.stream, "${}#{:02x}", data, checksum(data.as_bytes()))?;
+        self.stream.flush()
+    }
+
+    /// Checks, without blocking, whether GDB has sent an interrupt.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+
+        let mut buf = [0];
+        let result = match self.stream.read(&mut buf) {
+            Ok(0) => Ok(true),
+            Ok(_) => Ok(buf[0] == 0x03),
+            Err(ref x) if x.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(x) => Err(x),
+        };
+
+        self.stream.set_nonblocking(false)?;
+        result
+    }
+
+    /// Runs until something stops the machine or GDB interrupts it.
+    fn resume(&mut self) -> io::Result<String> {
+        loop {
+            for _ in 0..POLL_INTERVAL {
+                if let Some(x) = self.session.advance() {
+                    return Ok(stop_reply(&x));
+                }
+            }
+
+            let _ = io::stdout().flush();
+            if self.interrupted()? {
+                return Ok(String::from("S02"));
+            }
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        self.session.processor.registers().iter()
+            .enumerate()
+            .map(|(r, d)| encode_register(r, *d))
+            .collect()
+    }
+
+    fn write_registers(&mut self, args: &str) -> Option<String> {
+        let bytes = decode_hex(args)?;
+        if bytes.len() != REGISTERS_LEN {
+            return None;
+        }
+
+        let mut rest = &bytes[..];
+        for r in 0..8 {
+            let (value, remaining) = rest.split_at(register_len(r));
+            self.session.processor.set_register(r, decode_register(r, value)?);
+            rest = remaining;
+        }
+        Some(String::from("OK"))
+    }
+
+    fn read_register(&self, args: &str) -> Option<String> {
+        let r = hex(args)? as usize;
+        self.session.processor.registers().get(r).map(|d| encode_register(r, *d))
+    }
+
+    fn write_register(&mut self, args: &str) -> Option<String> {
+        let mut parts = args.splitn(2, '=');
+        let r = hex(parts.next()?)? as usize;
+        let bytes = decode_hex(parts.next()?)?;
+
+        if r >= 8 || bytes.len() != register_len(r) {
+            return None;
+        }
+        self.session.processor.set_register(r, decode_register(r, &bytes)?);
+        Some(String::from("OK"))
+    }
+
+    fn read_memory(&mut self, args: &str) -> Option<String> {
+        let (address, length) = address_length(args)?;
+        if address as u64 + length as u64 > 0x20000 {
+            return None;
+        }
+
+        let mut reply = String::new();
+        for byte in address..address + length {
+            let [high, low] = self.session.read((byte / 2) as u16).to_be_bytes();
+            reply.push_str(&format!("{:02x}", if byte % 2 == 0 { high } else { low }));
+        }
+        Some(reply)
+    }
+
+    fn write_memory(&mut self, args: &str) -> Option<String> {
+        let mut parts = args.splitn(2, ':');
+        let (address, length) = address_length(parts.next()?)?;
+        let bytes = decode_hex(parts.next()?)?;
+
+        if bytes.len() != length as usize || address as u64 + length as u64 > 0x20000 {
+            return None;
+        }
+
+        for (byte, value) in (address..address + length).zip(bytes) {
+            let word = (byte / 2) as u16;
+            let [high, low] = self.session.read(word).to_be_bytes();
+            let new = if byte % 2 == 0 { [value, low] } else { [high, value] };
+            self.session.write(word, u16::from_be_bytes(new));
+        }
+        Some(String::from("OK"))
+    }
+
+    /// Handles `Z` (insert) and `z` (remove) for breakpoints and watchpoints.
+    fn breakpoint(&mut self, insert: bool, args: &str) -> Option<String> {
+        let mut parts = args.splitn(3, ',');
+        let kind = parts.next()?;
+        let address = hex(parts.next()?)?;
+        let length = hex(parts.next()?)?.max(1);
+
+        let start = (address / 2) as u16;
+        let end = (address.saturating_add(length - 1) / 2) as u16;
+
+        let kind = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.session.breakpoints.insert(start, None);
+                } else {
+                    self.session.breakpoints.remove(&start);
+                }
+                return Some(String::from("OK"));
+            },
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return Some(String::new()),
+        };
+
+        let watchpoint = Watchpoint { start, end, kind };
+        let watchpoints = &mut self.session.memory.watchpoints;
+        if insert {
+            watchpoints.push(watchpoint);
+        } else if let Some(x) = watchpoints.iter().position(|d| *d == watchpoint) {
+            watchpoints.remove(x);
+        }
+        Some(String::from("OK"))
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return String::from("PacketSize=1000;qXfer:features:read+;ReverseStep+;ReverseContinue+");
+        }
+
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, length) = match address_length(args) {
+                Some(x) => x,
+                None => return String::from("E01"),
+            };
+
+            let start = (offset as usize).min(TARGET_XML.len());
+            let end = (start + length as usize).min(TARGET_XML.len());
+            let more = if end == TARGET_XML.len() { "l" } else { "m" };
+            return format!("{}{}", more, escape(&TARGET_XML[start..end]));
+        }
+
+        match packet {
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            _ => String::new(),
+        }
+    }
+
+    /// Answers one packet, or returns `None` when the connection should close.
+    fn handle(&mut self, packet: &str) -> io::Result<Option<String>> {
+        let error = || String::from("E01");
+        let (command, args) = match packet.chars().next() {
+            Some(x) => packet.split_at(x.len_utf8()),
+            None => return Ok(Some(String::new())),
+        };
+
+        let reply = match command {
+            "?" => self.last.clone(),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args).unwrap_or_else(error),
+            "p" => self.read_register(args).unwrap_or_else(error),
+            "P" => self.write_register(args).unwrap_or_else(error),
+            "m" => self.read_memory(args).unwrap_or_else(error),
+            "M" => self.write_memory(args).unwrap_or_else(error),
+            "s" | "c" => {
+                if !args.is_empty() {
+                    match hex(args) {
+                        Some(x) => self.session.processor.set_register(1, (x / 2) as u16),
+                        None => return Ok(Some(error())),
+                    }
+                }
+
+                let reply = if command == "s" {
+                    match self.session.advance() {
+                        Some(x) => stop_reply(&x),
+                        None => String::from("S05"),
+                    }
+                } else {
+                    self.resume()?
+                };
+
+                let _ = io::stdout().flush();
+                self.last = reply.clone();
+                reply
+            },
+            "b" if args == "s" || args == "c" => {
+                let stop = if args == "s" {
+                    self.session.reverse_advance()
+                } else {
+                    Some(self.session.reverse_run())
+                };
+
+                let reply = match stop {
+                    Some(x) => stop_reply(&x),
+                    None => String::from("S05"),
+                };
+                self.last = reply.clone();
+                reply
+            },
+            "Z" => self.breakpoint(true, args).unwrap_or_else(error),
+            "z" => self.breakpoint(false, args).unwrap_or_else(error),
+            "H" => String::from("OK"),
+            "k" => return Ok(None),
+            "D" => {
+                self.send("OK")?;
+                return Ok(None);
+            },
+            "q" => self.query(packet),
+            _ => String::new(),
+        };
+
+        Ok(Some(reply))
+    }
+}
+
+/// Waits for GDB to connect on `port` and serves it the remote serial
+/// protocol until it detaches.
+///
+/// The machine is word addressed, but GDB addresses bytes, so word `w` is
+/// presented as bytes `2w` (high) and `2w + 1` (low). Registers are sent high
+/// byte first as well, and `count`, the program counter, is sent as the 32-bit
+/// byte address `2 * count`.
+pub fn serve(session: Session, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("Listening for gdb on 127.0.0.1:{}", port);
+
+    let (stream, address) = listener.accept()?;
+    println!("Connected to {}", address);
+
+    let mut server = Server {
+        session,
+        stream,
+        last: String::from("S05"),
+    };
+
+    while let Some(packet) = server.packet()? {
+        match server.handle(&packet)? {
+            Some(x) => server.send(&x)?,
+            None => break,
+        }
+    }
+
+    Ok(())
+}
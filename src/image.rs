@@ -0,0 +1,139 @@
+use crate::isa::REGISTERS;
+
+/// Starts an image that has a header: "R16I" as two big-endian words.
+pub const MAGIC: [u16; 2] = [0x5231, 0x3649];
+pub const VERSION: u16 = 1;
+
+/// The words in a header with no register values.
+const HEADER_LEN: usize = 7;
+
+/// Set in the header's flags when it gives an entry point.
+const HAS_ENTRY: u16 = 1;
+
+/// A program image: the words to load, and optionally where to start running
+/// them and what to set registers to first.
+///
+/// An image only carries a header when it has an entry point or register
+/// values. The header is, one word each: `MAGIC`, the version, the length of
+/// the header in words, a flags word whose bit 0 says whether there is an entry
+/// point, the entry point, the number of register values and then each
+/// register number and its value. Since no opcode starts with `MAGIC`, images
+/// without a header are read as they are.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Image {
+    pub words: Vec<u16>,
+    /// An absolute address, not one relative to where the image is loaded.
+    pub entry: Option<u16>,
+    pub registers: Vec<(u16, u16)>,
+}
+
+impl Image {
+    /// An image with no header.
+    pub fn plain(words: Vec<u16>) -> Image {
+        Image {
+            words,
+            entry: None,
+            registers: vec![],
+        }
+    }
+
+    pub fn to_words(&self) -> Vec<u16> {
+        if self.entry.is_none() && self.registers.is_empty() {
+            return self.words.clone();
+        }
+
+        let len = HEADER_LEN + 2 * self.registers.len();
+        let flags = if self.entry.is_some() { HAS_ENTRY } else { 0 };
+
+        let mut words = Vec::with_capacity(len + self.words.len());
+        words.extend_from_slice(&MAGIC);
+        words.extend_from_slice(&[VERSION, len as u16, flags, self.entry.unwrap_or(0), self.registers.len() as u16]);
+        for (register, value) in self.registers.iter() {
+            words.extend_from_slice(&[*register, *value]);
+        }
+        words.extend_from_slice(&self.words);
+        words
+    }
+
+    pub fn from_words(words: Vec<u16>) -> Result<Image, String> {
+        if !words.starts_with(&MAGIC) {
+            return Ok(Image::plain(words));
+        }
+
+        if words.len() < HEADER_LEN {
+            return Err(String::from("image header is cut short"));
+        }
+        if words[2] != VERSION {
+            return Err(format!("image version {} is not supported, expected {}", words[2], VERSION));
+        }
+
+        let len = words[3] as usize;
+        let count = words[6] as usize;
+        if len != HEADER_LEN + 2 * count || len > words.len() {
+            return Err(format!("image header is {} words long, which does not fit {} register values", len, count));
+        }
+
+        let mut registers = vec![];
+        for pair in words[HEADER_LEN..len].chunks(2) {
+            if pair[0] as usize >= REGISTERS.len() {
+                return Err(format!("image header sets register {}, which does not exist", pair[0]));
+            }
+            registers.push((pair[0], pair[1]));
+        }
+
+        Ok(Image {
+            entry: if words[4] & HAS_ENTRY != 0 { Some(words[5]) } else { None },
+            registers,
+            words: words[len..].to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let image = Image {
+            words: vec![0x0001, 0x0002, 0x0003],
+            entry: Some(2),
+            registers: vec![(0, 'E' as u16), (5, 0x100)],
+        };
+        let words = image.to_words();
+        assert_eq!(words[..HEADER_LEN], [MAGIC[0], MAGIC[1], VERSION, 11, HAS_ENTRY, 2, 2]);
+        assert_eq!(words.len(), 11 + 3);
+        assert_eq!(Image::from_words(words), Ok(image));
+
+        let registers_only = Image {
+            words: vec![7],
+            entry: None,
+            registers: vec![(3, 0xFFFF)],
+        };
+        assert_eq!(Image::from_words(registers_only.to_words()), Ok(registers_only));
+    }
+
+    #[test]
+    fn plain_images_have_no_header() {
+        let image = Image::plain(vec![0x1234, 0x5678]);
+        assert_eq!(image.to_words(), [0x1234, 0x5678]);
+        assert_eq!(Image::from_words(vec![0x1234, 0x5678]), Ok(image));
+        assert_eq!(Image::from_words(vec![]), Ok(Image::plain(vec![])));
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let header = |rest: &[u16]| {
+            let mut words = MAGIC.to_vec();
+            words.extend_from_slice(rest);
+            Image::from_words(words)
+        };
+
+        assert!(header(&[VERSION, 7, 0]).is_err());
+        assert!(header(&[VERSION + 1, 7, 0, 0, 0]).is_err());
+        assert!(header(&[VERSION, 9, 0, 0, 0]).is_err());
+        assert!(header(&[VERSION, 9, 0, 0, 1]).is_err());
+        assert!(header(&[VERSION, 9, 0, 0, 1, REGISTERS.len() as u16, 0]).is_err());
+        assert!(header(&[VERSION, 9, 0, 0, 1, 0, 0]).is_ok());
+    }
+}
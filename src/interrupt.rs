@@ -0,0 +1,161 @@
+use modVM::*;
+use modVM::Query::*;
+use modVM::Response::*;
+use crate::bus::Inspect;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU16, Ordering};
+
+/// The interrupt the timer raises when its countdown expires.
+pub const TIMER_IRQ: u16 = 0;
+/// The interrupt the input device raises when a byte arrives.
+pub const INPUT_IRQ: u16 = 1;
+/// The interrupt the disk controller raises when a command finishes.
+pub const DISK_IRQ: u16 = 2;
+
+/// Where the vector table starts unless the program moves it. An image can
+/// fill it in with `.org 0xFFF0`; the words it pads the device window with
+/// are not loaded, so the controller keeps its defaults.
+pub const DEFAULT_VECTOR: u16 = 0xFFF0;
+
+struct Shared {
+    pending: AtomicU16,
+    mask: AtomicU16,
+    vector: AtomicU16,
+}
+
+/// The interrupt state shared by the devices that raise interrupts, the
+/// processor that takes them and the `Controller` that exposes them to
+/// programs. There are 16 interrupts, numbered by their bit in `pending`.
+///
+/// Interrupt `n` jumps to the address held in word `n` of the vector table.
+#[derive(Clone)]
+pub struct Interrupts(Arc<Shared>);
+
+impl Interrupts {
+    /// Nothing pending, every interrupt unmasked and the vector table at
+    /// `DEFAULT_VECTOR`.
+    pub fn new() -> Interrupts {
+        Interrupts(Arc::new(Shared {
+            pending: AtomicU16::new(0),
+            mask: AtomicU16::new(0xFFFF),
+            vector: AtomicU16::new(DEFAULT_VECTOR),
+        }))
+    }
+
+    /// A handle for a device to raise interrupt `irq` with.
+    pub fn line(&self, irq: u16) -> InterruptLine {
+        InterruptLine {
+            interrupts: self.clone(),
+            irq,
+        }
+    }
+
+    pub fn raise(&self, irq: u16) {
+        self.0.pending.fetch_or(1 << irq, Ordering::SeqCst);
+    }
+
+    /// Clears and returns the lowest numbered interrupt that is both pending
+    /// and unmasked.
+    pub fn take(&self) -> Option<u16> {
+        let mask = self.0.mask.load(Ordering::SeqCst);
+        let mut irq = None;
+
+        let _ = self.0.pending.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+            match pending & mask {
+                0 => None,
+                x => {
+                    irq = Some(x.trailing_zeros() as u16);
+                    Some(pending & !(x & x.wrapping_neg()))
+                },
+            }
+        });
+        irq
+    }
+
+    /// The address of the vector table.
+    pub fn vector(&self) -> u16 {
+        self.0.vector.load(Ordering::SeqCst)
+    }
+}
+
+impl Default for Interrupts {
+    fn default() -> Interrupts {
+        Interrupts::new()
+    }
+}
+
+/// Raises one particular interrupt.
+#[derive(Clone)]
+pub struct InterruptLine {
+    interrupts: Interrupts,
+    irq: u16,
+}
+
+impl InterruptLine {
+    pub fn raise(&self) {
+        self.interrupts.raise(self.irq);
+    }
+}
+
+/// Exposes the interrupt state to programs as three words: the pending
+/// interrupts at offset 0, where saving clears the bits that are set in the
+/// value, the mask at offset 1 and the vector table's address at offset 2.
+pub struct Controller(pub Interrupts);
+
+impl Peripheral<u16> for Controller {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            model: String::from("Interrupt Controller v.0.0.0"),
+        }
+    }
+
+    fn handle(&mut self, incoming: Query<u16>) -> Result<Response<u16>, u16> {
+        let shared = &(self.0).0;
+
+        Ok(match incoming {
+            LoadRequest(x) => match self.peek(x) {
+                Some(y) => Data(y),
+                None => Fail(0),
+            },
+            SaveRequest(x, 0) => {
+                shared.pending.fetch_and(!x, Ordering::SeqCst);
+                Good
+            },
+            SaveRequest(x, 1) => {
+                shared.mask.store(x, Ordering::SeqCst);
+                Good
+            },
+            SaveRequest(x, 2) => {
+                shared.vector.store(x, Ordering::SeqCst);
+                Good
+            },
+            _ => Fail(0),
+        })
+    }
+}
+
+impl Inspect for Controller {
+    fn peek(&self, offset: u16) -> Option<u16> {
+        let shared = &(self.0).0;
+
+        match offset {
+            0 => Some(shared.pending.load(Ordering::SeqCst)),
+            1 => Some(shared.mask.load(Ordering::SeqCst)),
+            2 => Some(shared.vector.load(Ordering::SeqCst)),
+            _ => None,
+        }
+    }
+
+    /// Unlike a save, offset 0 replaces the pending interrupts outright.
+    fn poke(&mut self, offset: u16, value: u16) -> bool {
+        let shared = &(self.0).0;
+
+        match offset {
+            0 => shared.pending.store(value, Ordering::SeqCst),
+            1 => shared.mask.store(value, Ordering::SeqCst),
+            2 => shared.vector.store(value, Ordering::SeqCst),
+            _ => return false,
+        }
+        true
+    }
+}
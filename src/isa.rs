@@ -0,0 +1,196 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    Register,
+    Immediate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Hlt,
+    Pnt,
+    Sav,
+    Set,
+    Cpy,
+    Add,
+    Sub,
+    Xor,
+    Nor,
+    And,
+    Lst,
+    Jnz,
+    Rti,
+    Ei,
+    Di,
+}
+
+use self::OperandKind::*;
+
+const REG_REG: &[OperandKind] = &[Register, Register];
+
+/// Every instruction as (opcode, encoding, mnemonic, operands). This table is
+/// the only place the instruction set is defined.
+const TABLE: [(Opcode, u16, &str, &[OperandKind]); 15] = [
+    (Opcode::Hlt, 0, "HLT", &[]),
+    (Opcode::Pnt, 1, "PNT", REG_REG),
+    (Opcode::Sav, 2, "SAV", REG_REG),
+    (Opcode::Set, 3, "SET", &[Register, Immediate]),
+    (Opcode::Cpy, 4, "CPY", REG_REG),
+    (Opcode::Add, 5, "ADD", REG_REG),
+    (Opcode::Sub, 6, "SUB", REG_REG),
+    (Opcode::Xor, 7, "XOR", REG_REG),
+    (Opcode::Nor, 8, "NOR", REG_REG),
+    (Opcode::And, 9, "AND", REG_REG),
+    (Opcode::Lst, 10, "LST", REG_REG),
+    (Opcode::Jnz, 11, "JNZ", REG_REG),
+    (Opcode::Rti, 12, "RTI", &[]),
+    (Opcode::Ei, 13, "EI", &[]),
+    (Opcode::Di, 14, "DI", &[]),
+];
+
+pub const REGISTERS: [&str; 8] = ["out", "count", "a", "b", "c", "d", "e", "f"];
+
+impl Opcode {
+    fn entry(self) -> &'static (Opcode, u16, &'static str, &'static [OperandKind]) {
+        TABLE.iter().find(|d| d.0 == self).unwrap()
+    }
+
+    pub fn from_u16(x: u16) -> Option<Opcode> {
+        TABLE.iter().find(|d| d.1 == x).map(|d| d.0)
+    }
+
+    pub fn from_mnemonic(s: &str) -> Option<Opcode> {
+        TABLE.iter().find(|d| d.2 == s).map(|d| d.0)
+    }
+
+    pub fn code(self) -> u16 {
+        self.entry().1
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        self.entry().2
+    }
+
+    pub fn operands(self) -> &'static [OperandKind] {
+        self.entry().3
+    }
+
+    /// The number of words the encoded instruction occupies.
+    pub fn size(self) -> u16 {
+        1 + self.operands().len() as u16
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    pub operands: [u16; 2],
+}
+
+impl Instruction {
+    pub fn encode(&self) -> Vec<u16> {
+        let mut out = vec![self.opcode.code()];
+        out.extend_from_slice(&self.operands[..self.opcode.operands().len()]);
+        out
+    }
+
+    /// Decodes the instruction at the start of `words`, or returns `None` if
+    /// the opcode is unknown, a register operand is out of range, or the
+    /// instruction is truncated.
+    pub fn decode(words: &[u16]) -> Option<Instruction> {
+        let opcode = Opcode::from_u16(*words.first()?)?;
+        let kinds = opcode.operands();
+        let mut operands = [0; 2];
+
+        for (i, kind) in kinds.iter().enumerate() {
+            operands[i] = *words.get(i + 1)?;
+
+            if *kind == Register && reg_name(operands[i]).is_none() {
+                return None;
+            }
+        }
+
+        Some(Instruction {
+            opcode,
+            operands,
+        })
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.opcode.mnemonic())?;
+
+        for (operand, kind) in self.operands.iter().zip(self.opcode.operands()) {
+            match (kind, reg_name(*operand)) {
+                (Register, Some(x)) => write!(f, " {}", x)?,
+                _ => write!(f, " {}", operand)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn get_reg(s: &str) -> Option<u16> {
+    REGISTERS.iter().position(|d| *d == s).map(|d| d as u16)
+}
+
+pub fn reg_name(r: u16) -> Option<&'static str> {
+    REGISTERS.get(r as usize).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_is_consistent() {
+        for (opcode, x, _, _) in TABLE.iter().cloned() {
+            assert_eq!(Opcode::from_u16(x), Some(opcode));
+            assert_eq!(opcode.code(), x);
+            assert_eq!(Opcode::from_mnemonic(opcode.mnemonic()), Some(opcode));
+        }
+        assert_eq!(Opcode::from_u16(TABLE.len() as u16), None);
+        assert_eq!(Opcode::from_mnemonic("hlt"), None);
+        assert_eq!(Opcode::Set.size(), 3);
+        assert_eq!(Opcode::Hlt.size(), 1);
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        for opcode in TABLE.iter().map(|x| x.0) {
+            let instruction = Instruction {
+                opcode,
+                operands: match opcode.operands().len() {
+                    0 => [0, 0],
+                    _ => [7, if opcode == Opcode::Set { 0xBEEF } else { 2 }],
+                },
+            };
+
+            let words = instruction.encode();
+            assert_eq!(words.len(), opcode.size() as usize);
+            assert_eq!(Instruction::decode(&words), Some(instruction));
+        }
+    }
+
+    #[test]
+    fn decode_rejects_bad_words() {
+        assert_eq!(Instruction::decode(&[]), None);
+        assert_eq!(Instruction::decode(&[TABLE.len() as u16, 0, 0]), None);
+        assert_eq!(Instruction::decode(&[5, 2, 8]), None);
+        assert_eq!(Instruction::decode(&[3, 2]), None);
+        assert!(Instruction::decode(&[3, 2, 0xFFFF]).is_some());
+    }
+
+    #[test]
+    fn display() {
+        let instruction = Instruction::decode(&[3, 1, 42]).unwrap();
+        assert_eq!(instruction.to_string(), "SET count 42");
+        assert_eq!(Instruction::decode(&[0]).unwrap().to_string(), "HLT");
+        assert_eq!(get_reg("f"), Some(7));
+        assert_eq!(reg_name(0), Some("out"));
+        assert_eq!(reg_name(8), None);
+    }
+}
@@ -1,435 +1,560 @@
-#![feature(int_to_from_bytes)]
+#![allow(non_snake_case)]
 extern crate modVM;
 use modVM::*;
 use modVM::Query::*;
 use modVM::Response::*;
+use std::fmt;
+use std::sync::mpsc::Sender;
+
+pub mod bus;
+pub mod console;
+pub mod disk;
+pub mod framebuffer;
+pub mod image;
+pub mod interrupt;
+pub mod isa;
+pub mod ram;
+pub mod rng;
+pub mod rom;
+pub mod snapshot;
+pub mod timer;
+pub mod trace;
+pub mod watch;
+
+use interrupt::Interrupts;
+use isa::{Instruction, Opcode};
+use snapshot::Snapshot;
+use trace::{Retired, TraceLevel, Tracer};
+
+/// Why the processor stopped, other than by halting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    IllegalOpcode { pc: u16, opcode: u16 },
+    BadRegister { pc: u16, opcode: Opcode, operand: u16 },
+    /// `opcode` is `None` when the instruction itself could not be fetched.
+    LoadFailed { pc: u16, opcode: Option<Opcode>, address: u16 },
+    SaveFailed { pc: u16, opcode: Opcode, address: u16 },
+    /// A save to read-only memory.
+    WriteProtected { pc: u16, opcode: Opcode, address: u16 },
+}
+
+impl Fault {
+    /// The code passed back to `modVM` when the processor stops, which `run`
+    /// also exits with. Codes 1 to 3 are left for `run`'s own errors.
+    pub fn code(&self) -> u16 {
+        match self {
+            Fault::IllegalOpcode { .. } => 4,
+            Fault::BadRegister { .. } => 5,
+            Fault::LoadFailed { .. } => 6,
+            Fault::SaveFailed { .. } => 7,
+            Fault::WriteProtected { .. } => 8,
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::IllegalOpcode { pc, opcode } => write!(f, "illegal opcode {} at {}", opcode, pc),
+            Fault::BadRegister { pc, opcode, operand } => write!(f, "`{}` at {} names register {}, which does not exist", opcode.mnemonic(), pc, operand),
+            Fault::LoadFailed { pc, opcode: None, address } => write!(f, "could not fetch the instruction at {} from {}", pc, address),
+            Fault::LoadFailed { pc, opcode: Some(x), address } => write!(f, "`{}` at {} could not load from {}", x.mnemonic(), pc, address),
+            Fault::SaveFailed { pc, opcode, address } => write!(f, "`{}` at {} could not save to {}", opcode.mnemonic(), pc, address),
+            Fault::WriteProtected { pc, opcode, address } => write!(f, "`{}` at {} tried to save to read-only memory at {}", opcode.mnemonic(), pc, address),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Halted { pc: u16 },
+    Faulted(Fault),
+}
+
+impl Outcome {
+    pub fn code(&self) -> u16 {
+        match self {
+            Outcome::Halted { .. } => 0,
+            Outcome::Faulted(x) => x.code(),
+        }
+    }
+}
+
+/// Everything about the processor that a program can observe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuState {
+    pub registers: [u16; 8],
+    /// Where `RTI` returns to.
+    pub saved_pc: u16,
+    pub interrupts_enabled: bool,
+    /// Set by `RTI` and `EI` so that the instruction after them runs before
+    /// any interrupt is taken.
+    pub interrupts_held: bool,
+}
 
 pub struct MainProcessor {
     registers: [u16; 8],
+    saved_pc: u16,
+    interrupts_enabled: bool,
+    interrupts_held: bool,
+    interrupts: Option<Interrupts>,
+    outcome: Option<Sender<Outcome>>,
+    snapshot: Option<Sender<Snapshot>>,
+    tracers: Vec<Box<dyn Tracer>>,
+    writes: Vec<(u16, u16)>,
 }
 
 impl MainProcessor {
     pub fn new() -> MainProcessor {
         MainProcessor {
             registers: [0; 8],
+            saved_pc: 0,
+            interrupts_enabled: false,
+            interrupts_held: false,
+            interrupts: None,
+            outcome: None,
+            snapshot: None,
+            tracers: vec![],
+            writes: vec![],
         }
     }
-}
 
-impl Processor<u16> for MainProcessor {
-    fn metadata(&self) -> Metadata {
-        Metadata {
-            model: String::from("RISC Processor v.0.0.0")
+    pub fn add_tracer(&mut self, tracer: Box<dyn Tracer>) {
+        if tracer.level() != TraceLevel::Off {
+            self.tracers.push(tracer);
         }
     }
 
-    fn exe_ins(&mut self, channels: &Vec<FrontEnd<u16>>) -> Result<(), u16> {
-        let ins = match channels[0].query(LoadRequest(self.registers[1])).unwrap() {
-            Data(x) => x,
-            _ => return Err(3),
+    pub fn registers(&self) -> [u16; 8] {
+        self.registers
+    }
+
+    pub fn set_register(&mut self, r: usize, value: u16) {
+        self.registers[r] = value;
+    }
+
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            registers: self.registers,
+            saved_pc: self.saved_pc,
+            interrupts_enabled: self.interrupts_enabled,
+            interrupts_held: self.interrupts_held,
+        }
+    }
+
+    pub fn restore(&mut self, state: CpuState) {
+        self.registers = state.registers;
+        self.saved_pc = state.saved_pc;
+        self.interrupts_enabled = state.interrupts_enabled;
+        self.interrupts_held = state.interrupts_held;
+    }
+
+    /// Takes interrupts from `interrupts` whenever they are enabled, which
+    /// they are not until the program executes `EI`.
+    pub fn connect_interrupts(&mut self, interrupts: Interrupts) {
+        self.interrupts = Some(interrupts);
+    }
+
+    /// Sends the reason the processor stopped to `sender` once it does.
+    pub fn report_outcome(&mut self, sender: Sender<Outcome>) {
+        self.outcome = Some(sender);
+    }
+
+    /// Sends a snapshot of the whole machine to `sender` once the processor stops.
+    pub fn report_snapshot(&mut self, sender: Sender<Snapshot>) {
+        self.snapshot = Some(sender);
+    }
+
+    /// Captures the registers and, word by word, the whole of `memory`.
+    /// Words that cannot be loaded are recorded as 0.
+    pub fn snapshot<M: MemoryPort>(&self, memory: &mut M) -> Snapshot {
+        let mut words = Box::new([0; 65536]);
+        for (address, word) in words.iter_mut().enumerate() {
+            *word = load(memory, address as u16).unwrap_or(0);
+        }
+
+        Snapshot {
+            state: self.state(),
+            memory: words,
+        }
+    }
+
+    /// Executes a single instruction or, if an interrupt is pending, enters
+    /// its handler instead. The instruction after `RTI` or `EI` always runs
+    /// first, so that a program can return from one handler, or enable
+    /// interrupts, and still get something done before the next.
+    pub fn step<M: MemoryPort>(&mut self, memory: &mut M) -> Result<(), Outcome> {
+        if !std::mem::replace(&mut self.interrupts_held, false) && self.interrupt(memory)? {
+            return Ok(());
+        }
+
+        let pc = self.registers[1];
+        let before = self.registers;
+        self.writes.clear();
+
+        let instruction = self.fetch(memory, pc)?;
+        self.execute(memory, pc, instruction)?;
+
+        if !self.tracers.is_empty() {
+            let event = Retired {
+                pc,
+                instruction,
+                before,
+                after: self.registers,
+                writes: std::mem::take(&mut self.writes),
+            };
+
+            for tracer in self.tracers.iter_mut() {
+                tracer.retire(&event);
+            }
+
+            self.writes = event.writes;
+        }
+
+        Ok(())
+    }
+
+    /// Jumps to the handler for the next pending interrupt, if interrupts are
+    /// enabled, saving `count` for `RTI` and disabling interrupts until then.
+    /// Handlers do not nest.
+    fn interrupt<M: MemoryPort>(&mut self, memory: &mut M) -> Result<bool, Outcome> {
+        if !self.interrupts_enabled {
+            return Ok(false);
+        }
+
+        let interrupts = match &self.interrupts {
+            Some(x) => x,
+            None => return Ok(false),
+        };
+
+        let irq = match interrupts.take() {
+            Some(x) => x,
+            None => return Ok(false),
         };
 
-        println!("Items: {:?}", self.registers);
+        let pc = self.registers[1];
+        let address = interrupts.vector().wrapping_add(irq);
 
-        let topF = match channels[0].query(LoadRequest(self.registers[7])).unwrap() {
-            Data(x) => x,
-            _ => return Err(3),
+        self.registers[1] = match load(memory, address) {
+            Some(x) => x,
+            None => return Err(Outcome::Faulted(Fault::LoadFailed { pc, opcode: None, address })),
         };
+        self.saved_pc = pc;
+        self.interrupts_enabled = false;
+        Ok(true)
+    }
 
-        let topE = match channels[0].query(LoadRequest(self.registers[6])).unwrap() {
-            Data(x) => x,
-            _ => return Err(3),
+    fn fetch<M: MemoryPort>(&self, memory: &mut M, pc: u16) -> Result<Instruction, Outcome> {
+        let ins = match load(memory, pc) {
+            Some(x) => x,
+            None => return Err(Outcome::Faulted(Fault::LoadFailed { pc, opcode: None, address: pc })),
         };
 
-        println!("\nVal at f ({}): {}, e ({}): {}\n", self.registers[7], topF, self.registers[6], topE);
-
-        match ins {
-            0 => Err(0),
-            1 => { // point instruction
-                let args = (
-                        match channels[0].query(LoadRequest(self.registers[1]+1)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                        match channels[0].query(LoadRequest(self.registers[1]+2)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                    );
-
-                self.registers[1] += 3;
-
-                let loc = match self.registers.get(args.0 as usize) {
-                    Some(x) => x,
-                    None => return Err(2),
-                };
+        let opcode = match Opcode::from_u16(ins) {
+            Some(x) => x,
+            None => return Err(Outcome::Faulted(Fault::IllegalOpcode { pc, opcode: ins })),
+        };
 
-                let data = match channels[0].query(LoadRequest(*loc)).unwrap() {
-                    Data(x) => x,
-                    _ => return Err(3),
-                };
+        if opcode == Opcode::Hlt {
+            return Err(Outcome::Halted { pc });
+        }
 
-                match self.registers.get_mut(args.1 as usize) {
-                    Some(x) => *x = data,
-                    None => return Err(2),
-                };
+        let mut operands = [0; 2];
+        for (i, arg) in operands.iter_mut().take(opcode.operands().len()).enumerate() {
+            let address = pc.wrapping_add(1 + i as u16);
+            *arg = match load(memory, address) {
+                Some(x) => x,
+                None => return Err(Outcome::Faulted(Fault::LoadFailed { pc, opcode: Some(opcode), address })),
+            };
+        }
 
-                Ok(())
-            },
-            2 => { // save instruction
-                let args = (
-                        match channels[0].query(LoadRequest(self.registers[1]+1)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                        match channels[0].query(LoadRequest(self.registers[1]+2)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                    );
-                
-                self.registers[1] += 3;
-
-                let data = match self.registers.get(args.0 as usize) {
-                    Some(x) => x,
-                    None => return Err(2),
-                };
+        Ok(Instruction {
+            opcode,
+            operands,
+        })
+    }
 
-                let loc = match self.registers.get(args.1 as usize) {
-                    Some(x) => x,
-                    None => return Err(2),
-                };
+    fn execute<M: MemoryPort>(&mut self, memory: &mut M, pc: u16, instruction: Instruction) -> Result<(), Outcome> {
+        let Instruction { opcode, operands: args } = instruction;
+        let load_failed = |address| Outcome::Faulted(Fault::LoadFailed { pc, opcode: Some(opcode), address });
 
-                channels[0].query(SaveRequest(*data, *loc)).unwrap();
+        self.registers[1] = pc.wrapping_add(opcode.size());
 
+        let bad_register = |r| Outcome::Faulted(Fault::BadRegister { pc, opcode, operand: r });
+        let reg = |registers: &[u16; 8], r: u16| registers.get(r as usize).cloned().ok_or_else(|| bad_register(r));
+
+        match opcode {
+            Opcode::Hlt => Err(Outcome::Halted { pc }),
+            Opcode::Pnt => {
+                let loc = reg(&self.registers, args[0])?;
+                let data = load(memory, loc).ok_or_else(|| load_failed(loc))?;
+                *self.registers.get_mut(args[1] as usize).ok_or_else(|| bad_register(args[1]))? = data;
                 Ok(())
             },
-            3 => { // set instruction
-                let args = (
-                        match channels[0].query(LoadRequest(self.registers[1]+1)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                        match channels[0].query(LoadRequest(self.registers[1]+2)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                    );
-                
-                self.registers[1] += 3;
-
-                match self.registers.get_mut(args.0 as usize) {
-                    Some(x) => {
-                        *x = args.1;
+            Opcode::Sav => {
+                let data = reg(&self.registers, args[0])?;
+                let loc = reg(&self.registers, args[1])?;
+                match memory.query(SaveRequest(data, loc)) {
+                    Fail(rom::WRITE_PROTECTED) => Err(Outcome::Faulted(Fault::WriteProtected { pc, opcode, address: loc })),
+                    Fail(_) => Err(Outcome::Faulted(Fault::SaveFailed { pc, opcode, address: loc })),
+                    _ => {
+                        if !self.tracers.is_empty() {
+                            self.writes.push((loc, data));
+                        }
                         Ok(())
                     },
-                    None => Err(2),
                 }
             },
-            4 => { // copy instruction
-                let args = (
-                        match channels[0].query(LoadRequest(self.registers[1]+1)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                        match channels[0].query(LoadRequest(self.registers[1]+2)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                    );
-                
-                self.registers[1] += 3;
-
-                println!("Copying {} to {}", args.0, args.1);
-
-                let data = match self.registers.get(args.0 as usize) {
-                    Some(x) => *x,
-                    None => return Err(2),
-                };
-
-                match self.registers.get_mut(args.1 as usize) {
-                    Some(x) => {
-                        *x = data;
-                        Ok(())
-                    },
-                    None => Err(2),
-                }
+            Opcode::Set => {
+                *self.registers.get_mut(args[0] as usize).ok_or_else(|| bad_register(args[0]))? = args[1];
+                Ok(())
             },
-            5 => { // add instruction
-                let args = (
-                        match channels[0].query(LoadRequest(self.registers[1]+1)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                        match channels[0].query(LoadRequest(self.registers[1]+2)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                    );
-                
-                self.registers[1] += 3;
-
-                let data = (
-                    match self.registers.get(args.0 as usize) {
-                        Some(x) => x,
-                        None => return Err(2),
-                    },
-                    match self.registers.get(args.1 as usize) {
-                        Some(x) => x,
-                        None => return Err(2),
-                    },
-                );
-
-                println!("{} + {}", data.0, data.1);
-
-                self.registers[0] = *data.0 + *data.1;
-
+            Opcode::Cpy => {
+                let data = reg(&self.registers, args[0])?;
+                *self.registers.get_mut(args[1] as usize).ok_or_else(|| bad_register(args[1]))? = data;
                 Ok(())
             },
-            6 => { // sub instruction
-                let args = (
-                        match channels[0].query(LoadRequest(self.registers[1]+1)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                        match channels[0].query(LoadRequest(self.registers[1]+2)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                    );
-                
-                self.registers[1] += 3;
-
-                let data = (
-                    match self.registers.get(args.0 as usize) {
-                        Some(x) => x,
-                        None => return Err(2),
-                    },
-                    match self.registers.get(args.1 as usize) {
-                        Some(x) => x,
-                        None => return Err(2),
-                    },
-                );
-
-                println!("{} - {}", data.0, data.1);
-
-                self.registers[0] = *data.0 - *data.1;
-
+            Opcode::Add | Opcode::Sub | Opcode::Xor | Opcode::Nor | Opcode::And | Opcode::Lst => {
+                let data = (reg(&self.registers, args[0])?, reg(&self.registers, args[1])?);
+
+                self.registers[0] = match opcode {
+                    Opcode::Add => data.0.wrapping_add(data.1),
+                    Opcode::Sub => data.0.wrapping_sub(data.1),
+                    Opcode::Xor => data.0 ^ data.1,
+                    Opcode::Nor => !(data.0 | data.1),
+                    Opcode::And => data.0 & data.1,
+                    _ => (data.0 < data.1) as u16,
+                };
                 Ok(())
             },
-            7 => { // xor instruction
-                let args = (
-                        match channels[0].query(LoadRequest(self.registers[1]+1)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                        match channels[0].query(LoadRequest(self.registers[1]+2)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                    );
-                
-                self.registers[1] += 3;
-
-                let data = (
-                    match self.registers.get(args.0 as usize) {
-                        Some(x) => x,
-                        None => return Err(2),
-                    },
-                    match self.registers.get(args.1 as usize) {
-                        Some(x) => x,
-                        None => return Err(2),
-                    },
-                );
-
-                self.registers[0] = *data.0 ^ *data.1;
+            Opcode::Jnz => {
+                let data = (reg(&self.registers, args[0])?, reg(&self.registers, args[1])?);
 
+                if data.0 != 0 {
+                    self.registers[1] = data.1;
+                };
                 Ok(())
             },
-            8 => { // nor instruction
-                let args = (
-                        match channels[0].query(LoadRequest(self.registers[1]+1)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                        match channels[0].query(LoadRequest(self.registers[1]+2)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                    );
-                
-                self.registers[1] += 3;
-
-                let data = (
-                    match self.registers.get(args.0 as usize) {
-                        Some(x) => x,
-                        None => return Err(2),
-                    },
-                    match self.registers.get(args.1 as usize) {
-                        Some(x) => x,
-                        None => return Err(2),
-                    },
-                );
-
-                self.registers[0] = !(*data.0 | *data.1);
-
+            Opcode::Rti => {
+                self.registers[1] = self.saved_pc;
+                self.interrupts_enabled = true;
+                self.interrupts_held = true;
                 Ok(())
             },
-           9 => { // and instruction
-                let args = (
-                        match channels[0].query(LoadRequest(self.registers[1]+1)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                        match channels[0].query(LoadRequest(self.registers[1]+2)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                    );
-                
-                self.registers[1] += 3;
-
-                let data = (
-                    match self.registers.get(args.0 as usize) {
-                        Some(x) => x,
-                        None => return Err(2),
-                    },
-                    match self.registers.get(args.1 as usize) {
-                        Some(x) => x,
-                        None => return Err(2),
-                    },
-                );
-
-                self.registers[0] = *data.0 & *data.1;
-
+            Opcode::Ei => {
+                self.interrupts_enabled = true;
+                self.interrupts_held = true;
                 Ok(())
             },
-            10 => { // less than instruction
-                let args = (
-                        match channels[0].query(LoadRequest(self.registers[1]+1)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                        match channels[0].query(LoadRequest(self.registers[1]+2)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                    );
-                
-                self.registers[1] += 3;
-
-                let data = (
-                    match self.registers.get(args.0 as usize) {
-                        Some(x) => x,
-                        None => return Err(2),
-                    },
-                    match self.registers.get(args.1 as usize) {
-                        Some(x) => x,
-                        None => return Err(2),
-                    },
-                );
-
-                self.registers[0] = (*data.0 < *data.1) as u16;
-
+            Opcode::Di => {
+                self.interrupts_enabled = false;
                 Ok(())
             },
-            11 => { // jump not zero instruction
-                let args = (
-                        match channels[0].query(LoadRequest(self.registers[1]+1)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                        match channels[0].query(LoadRequest(self.registers[1]+2)).unwrap() {
-                            Data(x) => x,
-                            _ => return Err(3),
-                        },
-                    );
-                
-                self.registers[1] += 3;
-
-                let data = (
-                    match self.registers.get(args.0 as usize) {
-                        Some(x) => x,
-                        None => return Err(2),
-                    },
-                    match self.registers.get(args.1 as usize) {
-                        Some(x) => x,
-                        None => return Err(2),
-                    },
-                );
+        }
+    }
+}
 
-                if *data.0 != 0 {
-                    self.registers[1] = *data.1;
-                };
+impl Default for MainProcessor {
+    fn default() -> MainProcessor {
+        MainProcessor::new()
+    }
+}
 
-                Ok(())
+impl Processor<u16> for MainProcessor {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            model: String::from("RISC Processor v.0.0.0")
+        }
+    }
+
+    fn exe_ins(&mut self, channels: &Vec<FrontEnd<u16>>) -> Result<(), u16> {
+        match self.step(&mut &channels[..]) {
+            Ok(()) => Ok(()),
+            Err(x) => {
+                if let Some(sender) = &self.outcome {
+                    let _ = sender.send(x);
+                }
+                Err(x.code())
             },
-            x => {
-                    println!("{}", x);
-                    Err(1)
-                },
         }
     }
+
+    fn halt(&mut self, channels: &Vec<FrontEnd<u16>>) -> Result<(), u16> {
+        for tracer in self.tracers.iter_mut() {
+            tracer.flush();
+        }
+
+        if let Some(sender) = &self.snapshot {
+            let _ = sender.send(self.snapshot(&mut &channels[..]));
+        }
+        Ok(())
+    }
 }
 
-pub struct PrintMemory {
-    mem: Box<[u16; 65536]>,
+/// Where the processor sends its memory queries: the machine's channels when
+/// it runs under `modVM`, or a peripheral it drives directly (see `Direct`).
+pub trait MemoryPort {
+    fn query(&mut self, query: Query<u16>) -> Response<u16>;
 }
 
-impl PrintMemory {
-    pub fn from_data(mem: Box<[u16; 65536]>) -> PrintMemory {
-        PrintMemory {
-            mem,
+impl MemoryPort for &[FrontEnd<u16>] {
+    fn query(&mut self, query: Query<u16>) -> Response<u16> {
+        self[0].query(query).unwrap()
+    }
+}
+
+/// Connects the processor straight to a peripheral, with no threads or
+/// channels in between, so that it can be single-stepped.
+pub struct Direct<'a, P: Peripheral<u16>>(pub &'a mut P);
+
+impl<'a, P: Peripheral<u16>> MemoryPort for Direct<'a, P> {
+    fn query(&mut self, query: Query<u16>) -> Response<u16> {
+        match self.0.handle(query) {
+            Ok(x) => x,
+            Err(x) => Fail(x),
         }
     }
 }
 
-impl Peripheral<u16> for PrintMemory {
-    fn metadata(&self) -> Metadata {
-        Metadata {
-            model: String::from("Standard PO Memory v.0.0.0"),
+fn load<M: MemoryPort>(memory: &mut M, address: u16) -> Option<u16> {
+    match memory.query(LoadRequest(address)) {
+        Data(x) => Some(x),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, Inspect};
+    use crate::interrupt::{Controller, DEFAULT_VECTOR};
+    use crate::ram::Ram;
+    use crate::rom::Rom;
+
+    /// `EI`, `SET a 5`, `SET b 6`, `HLT`, with a handler at 100 for
+    /// interrupt 0 that runs `SET c 7` and `RTI`.
+    fn machine() -> (MainProcessor, Ram, Interrupts) {
+        let mut memory = Ram::new(65536);
+        let program: [&[u16]; 3] = [&[13, 3, 2, 5, 3, 3, 6, 0], &[3, 4, 7, 12], &[100]];
+        for (base, words) in [0, 100, DEFAULT_VECTOR].iter().zip(program.iter()) {
+            for (i, word) in words.iter().enumerate() {
+                memory.handle(SaveRequest(*word, base + i as u16)).unwrap();
+            }
         }
+
+        let interrupts = Interrupts::new();
+        let mut processor = MainProcessor::new();
+        processor.connect_interrupts(interrupts.clone());
+        (processor, memory, interrupts)
     }
 
-    fn handle(&mut self, incoming: Query<u16>) -> Result<Response<u16>, u16> {
-        Ok(match incoming {
-            LoadRequest(x) => {
-                match self.mem.get(x as usize) {
-                    Some(y) => {
-                        Data(*y)
-                    },
-                    None => Fail(0),
-                }
-            },
-            SaveRequest(x, y) => {
-                match self.mem.get_mut(y as usize) {
-                    Some(z) => {
-                        *z = x;
-                        Good
-                    },
-                    None => Fail(0),
-                }
-            },
-        })
+    fn pc(processor: &MainProcessor) -> u16 {
+        processor.registers()[1]
     }
 
-    fn cycle(&mut self) -> Result<(), u16> {
-        let flag = self.mem[8080];
+    #[test]
+    fn runs_to_a_halt() {
+        let (mut processor, mut memory, _) = machine();
+        for _ in 0..3 {
+            processor.step(&mut Direct(&mut memory)).unwrap();
+        }
+        assert_eq!(processor.step(&mut Direct(&mut memory)), Err(Outcome::Halted { pc: 7 }));
+        assert_eq!(processor.registers()[2..4], [5, 6]);
+    }
+
+    #[test]
+    fn interrupts_wait_for_ei() {
+        let (mut processor, mut memory, interrupts) = machine();
+        interrupts.raise(0);
+        processor.set_register(1, 1);
+        processor.step(&mut Direct(&mut memory)).unwrap();
+        assert_eq!(pc(&processor), 4);
+        assert!(!processor.state().interrupts_enabled);
+    }
+
+    #[test]
+    fn interrupt_entry_and_rti() {
+        let (mut processor, mut memory, interrupts) = machine();
+        processor.step(&mut Direct(&mut memory)).unwrap();
+        assert!(processor.state().interrupts_enabled);
+
+        // the instruction after `EI` runs first
+        interrupts.raise(0);
+        processor.step(&mut Direct(&mut memory)).unwrap();
+        assert_eq!((pc(&processor), processor.registers()[2]), (4, 5));
+
+        processor.step(&mut Direct(&mut memory)).unwrap();
+        let state = processor.state();
+        assert_eq!((pc(&processor), state.saved_pc, state.interrupts_enabled), (100, 4, false));
+
+        // handlers do not nest
+        interrupts.raise(0);
+        processor.step(&mut Direct(&mut memory)).unwrap();
+        assert_eq!((pc(&processor), processor.registers()[4]), (103, 7));
+
+        processor.step(&mut Direct(&mut memory)).unwrap();
+        assert_eq!((pc(&processor), processor.state().interrupts_enabled), (4, true));
+
+        // and so does the one after `RTI`, even with another interrupt pending
+        processor.step(&mut Direct(&mut memory)).unwrap();
+        assert_eq!((pc(&processor), processor.registers()[3]), (7, 6));
 
-        if flag == 2 {
-            let data = self.mem[8081];
-            let [upper, lower] = data.to_be_bytes();
+        processor.step(&mut Direct(&mut memory)).unwrap();
+        assert_eq!((pc(&processor), processor.state().saved_pc), (100, 7));
+    }
 
-            print!("{}{}", upper as char, lower as char);
-            self.mem[8080] = 0;
-        } else if flag != 0 {
-            let data = self.mem[8081];
-            let [_, lower] = data.to_be_bytes();
+    #[test]
+    fn masked_interrupts_stay_pending() {
+        let (mut processor, mut memory, interrupts) = machine();
+        let mut controller = Controller(interrupts.clone());
+        controller.handle(SaveRequest(0xFFFE, 1)).unwrap();
 
-            print!("{}", lower as char);
-            self.mem[8080] = 0;
+        for _ in 0..2 {
+            processor.step(&mut Direct(&mut memory)).unwrap();
         }
+        interrupts.raise(0);
+        processor.step(&mut Direct(&mut memory)).unwrap();
+        assert_eq!(pc(&processor), 7);
 
-        Ok(())
+        controller.handle(SaveRequest(0xFFFF, 1)).unwrap();
+        processor.step(&mut Direct(&mut memory)).unwrap();
+        assert_eq!((pc(&processor), processor.state().saved_pc), (100, 7));
+    }
+
+    #[test]
+    fn unreadable_vectors_fault() {
+        let (mut processor, _, interrupts) = machine();
+        let mut small = Ram::new(8);
+        for (address, word) in [13, 3, 2, 0].iter().enumerate() {
+            small.poke(address as u16, *word);
+        }
+        for _ in 0..2 {
+            processor.step(&mut Direct(&mut small)).unwrap();
+        }
+
+        interrupts.raise(0);
+        let fault = Fault::LoadFailed { pc: 4, opcode: None, address: DEFAULT_VECTOR };
+        assert_eq!(processor.step(&mut Direct(&mut small)), Err(Outcome::Faulted(fault)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn saves_to_rom_are_write_protected() {
+        // `SET a 20`, `SAV b a`
+        let mut memory = Bus::new();
+        memory.attach("ram", 0, 32, Box::new(Ram::new(32))).unwrap();
+        memory.overlay("rom", 20, 4, Box::new(Rom::new(vec![1, 2, 3, 4]))).unwrap();
+        memory.load(0, &[3, 2, 20, 2, 3, 2]);
+
+        let mut processor = MainProcessor::new();
+        processor.step(&mut Direct(&mut memory)).unwrap();
+        let fault = Fault::WriteProtected { pc: 3, opcode: Opcode::Sav, address: 20 };
+        assert_eq!(processor.step(&mut Direct(&mut memory)), Err(Outcome::Faulted(fault)));
+        assert_eq!(memory.peek(20), Some(1));
+
+        // past the end of the ROM is just unowned
+        processor.set_register(1, 3);
+        processor.set_register(2, 40);
+        let fault = Fault::SaveFailed { pc: 3, opcode: Opcode::Sav, address: 40 };
+        assert_eq!(processor.step(&mut Direct(&mut memory)), Err(Outcome::Faulted(fault)));
+    }
+}
@@ -1,12 +1,31 @@
-#![feature(int_to_from_bytes)]
 extern crate RISC_16_bit;
 extern crate modVM;
 use RISC_16_bit::*;
-use std::num::Wrapping;
+use RISC_16_bit::bus::{Bus, BOOT_BASE, DISK_BASE, FRAMEBUFFER_BASE};
+use RISC_16_bit::console::Input;
+use RISC_16_bit::disk::Disk;
+use RISC_16_bit::framebuffer::{self, Framebuffer, Output};
+use RISC_16_bit::image::Image;
+use RISC_16_bit::interrupt::{Interrupts, DISK_IRQ};
+use RISC_16_bit::rng::DEFAULT_SEED;
+use RISC_16_bit::rom::Rom;
+use RISC_16_bit::snapshot::Snapshot;
+use RISC_16_bit::trace::{JsonTracer, PrintTracer};
+use std::collections::HashMap;
 use std::{process, env};
-use std::fs::{read, read_to_string, write};
+use std::fs::{read, read_to_string, write, File};
+use std::io::BufWriter;
 use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::channel;
 mod compiler;
+mod condition;
+mod config;
+mod debugger;
+mod disasm;
+mod gdbserver;
+mod options;
+mod expr;
 mod scc;
 
 fn read_u16(bytestream: &[u8]) -> Vec<u16> {
@@ -20,13 +39,101 @@ fn read_u16(bytestream: &[u8]) -> Vec<u16> {
         .collect()
 }
 
-fn load(mem: &mut Box<[u16; 65536]>, program: &[u16], offset: u16) {
-    for (index, value) in program.iter().enumerate() {
-        let offset_pos = (Wrapping(index) + Wrapping(offset as usize)).0;
-        mem[offset_pos] = *value;
+/// Loads `program` straight into memory at `offset`, warning about any nonzero
+/// words that land where there is no memory to hold them, such as device
+/// registers or ROM. Those words are left out.
+fn load(bus: &mut Bus, name: &str, program: &[u16], offset: u16) {
+    for (start, end) in bus.load(offset, program) {
+        println!("Application WARNING: `{}` was not loaded into {}..{}, which is not RAM", name, start, end);
     }
 }
 
+/// Reads an image file, splitting off its header if it has one.
+fn read_image(path: &str) -> Result<Image, String> {
+    match read(path) {
+        Ok(x) => Image::from_words(read_u16(&x)).map_err(|x| format!("{}: {}", path, x)),
+        Err(x) => Err(format!("{}: {}", path, x)),
+    }
+}
+
+/// Reads a program image, or assembles it first if it is a `.rasm` source,
+/// in which case its labels are returned too.
+fn read_program(path: &str) -> Result<(Image, HashMap<String, u16>), String> {
+    if path.ends_with(".rasm") {
+        let source = match read_to_string(path) {
+            Ok(x) => x,
+            Err(x) => return Err(format!("Application ERROR: {}", x)),
+        };
+
+        match compiler::assemble(&source, path) {
+            Ok(x) => Ok((x.image(), x.symbols)),
+            Err(x) => {
+                let rendered: Vec<String> = x.iter()
+                    .map(|d| {
+                        d.render(&source)
+                    })
+                    .collect();
+                Err(rendered.join("\n"))
+            },
+        }
+    } else {
+        match read_image(path) {
+            Ok(x) => Ok((x, HashMap::new())),
+            Err(x) => Err(format!("Application ERROR: {}", x)),
+        }
+    }
+}
+
+/// Sets the registers `image` asks for and starts it at its entry point, or
+/// at 0 if it has none.
+fn start(processor: &mut MainProcessor, image: &Image) {
+    for (register, value) in image.registers.iter() {
+        processor.set_register(*register as usize, *value);
+    }
+    processor.set_register(1, image.entry.unwrap_or(0));
+}
+
+/// Works out where `run`'s input device reads from.
+fn run_input(options: &options::RunOptions) -> Result<Input, String> {
+    if let Some(path) = &options.input_file {
+        return match read(path) {
+            Ok(x) => Ok(Input::Bytes(x)),
+            Err(x) => Err(format!("{}: {}", path, x)),
+        };
+    }
+
+    if let Some(text) = &options.input_str {
+        return compiler::unescape(text)?.iter()
+            .map(|d| {
+                if *d <= 0xFF {
+                    Ok(*d as u8)
+                } else {
+                    Err(format!("`--input-str` can only contain bytes, found {}", d))
+                }
+            })
+            .collect::<Result<Vec<u8>, String>>()
+            .map(Input::Bytes);
+    }
+
+    Ok(Input::Stdin)
+}
+
+/// Stops the terminal buffering lines and echoing them, returning its
+/// previous settings, or `None` if stdin is not a terminal.
+fn raw_terminal() -> Option<String> {
+    let saved = Command::new("stty").arg("-g").stdin(Stdio::inherit()).output().ok()?;
+    if !saved.status.success() {
+        return None;
+    }
+
+    Command::new("stty").args(["-icanon", "-echo"]).stdin(Stdio::inherit()).status().ok()?;
+    Some(String::from_utf8_lossy(&saved.stdout).trim().to_string())
+}
+
+fn restore_terminal(settings: &str) {
+    let _ = Command::new("stty").arg(settings).stdin(Stdio::inherit()).status();
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     if args.len() < 3 {
@@ -40,30 +147,194 @@ fn main() {
                 process::exit(1);
             }
             match compiler::compile(Path::new(&args[2]), Path::new(&args[3])) {
-                Ok(x) => {
+                Ok(_) => {
                     println!("Finished Compilation.");
                     process::exit(0);
                 },
                 Err(x) => {
-                    println!("Compilation ERROR: {}", x);
+                    println!("{}", x);
                     process::exit(2);
                 },
             }
         },
         "run" => {
-            let data = match read(&args[2]) {
-                Ok(x) => read_u16(&x),
+            let options = match options::parse_run(&args[2..]) {
+                Ok(x) => x,
+                Err(x) => {
+                    println!("Argument ERROR: {}", x);
+                    process::exit(1);
+                },
+            };
+
+            let interrupts = Interrupts::new();
+            let mut processor = MainProcessor::new();
+            processor.connect_interrupts(interrupts.clone());
+
+            let input = match run_input(&options) {
+                Ok(x) => x,
                 Err(x) => {
                     println!("Application ERROR: {}", x);
                     process::exit(3);
                 },
             };
 
-            let mut mem = Box::new([0; 65536]);
-            load(&mut mem, &data, 0);
+            let context = config::Context {
+                input: input.clone(),
+                interrupts,
+                seed: options.seed,
+            };
+
+            let mut memory = match &options.machine {
+                Some(path) => match config::load(path, &context) {
+                    Ok(x) => x,
+                    Err(x) => {
+                        println!("Application ERROR: {}", x);
+                        process::exit(3);
+                    },
+                },
+                None => Bus::standard(input.clone(), &context.interrupts, context.seed),
+            };
+
+            if let Some(path) = &options.disk {
+                let result = Disk::open(Path::new(path), memory.dma())
+                    .map_err(|x| format!("{}: {}", path, x))
+                    .and_then(|mut disk| {
+                        disk.connect(context.interrupts.line(DISK_IRQ));
+                        memory.attach("disk", DISK_BASE, 4, Box::new(disk))
+                    });
+
+                if let Err(x) = result {
+                    println!("Application ERROR: {}", x);
+                    process::exit(3);
+                }
+            }
+
+            let output = match &options.fb_dump {
+                Some(x) => Some(Output::Dump(x.into())),
+                None if options.framebuffer => Some(Output::Terminal),
+                None => None,
+            };
+
+            let display = output.map(|output| {
+                let result = Framebuffer::new(output, options.fb_refresh)
+                    .map_err(|x| x.to_string())
+                    .and_then(|x| {
+                        let display = x.display();
+                        let len = framebuffer::CELLS as u32;
+                        memory.overlay("framebuffer", FRAMEBUFFER_BASE, len, Box::new(x)).map(|_| display)
+                    });
+
+                match result {
+                    Ok(x) => x,
+                    Err(x) => {
+                        println!("Application ERROR: {}", x);
+                        process::exit(3);
+                    },
+                }
+            });
+
+            // the boot ROM's header, if it has one, says where in it to start, which
+            // must be an address inside the ROM as it is placed
+            let boot = options.boot_rom.as_ref().map(|path| {
+                let base = options.boot_addr.unwrap_or(BOOT_BASE);
+                let result = read_image(path).and_then(|x| {
+                    let rom = Rom::new(x.words);
+                    let len = rom.len() as u32;
+                    memory.overlay("boot rom", base, len, Box::new(rom))?;
+                    match x.entry {
+                        Some(y) if !(base as u32..base as u32 + len).contains(&(y as u32)) =>
+                            Err(format!("{}: entry point {} is outside the boot rom at {}..{}", path, y, base, base as u32 + len)),
+                        y => Ok((y.unwrap_or(base), x.registers)),
+                    }
+                });
+
+                match result {
+                    Ok(x) => x,
+                    Err(x) => {
+                        println!("Application ERROR: {}", x);
+                        process::exit(3);
+                    },
+                }
+            });
+
+            match (&options.program, &options.resume) {
+                (Some(path), _) => {
+                    let base = options.load_addr.unwrap_or(0);
+                    let images = options.images.iter().map(|(path, address)| (path, *address));
+
+                    let mut program = None;
+                    for (path, address) in Some((path, base)).into_iter().chain(images) {
+                        let image = match read_image(path) {
+                            Ok(x) => x,
+                            Err(x) => {
+                                println!("Application ERROR: {}", x);
+                                process::exit(3);
+                            },
+                        };
+
+                        load(&mut memory, path, &image.words, address);
+                        program.get_or_insert(image);
+                    }
+
+                    // only the program's own header counts, not those of further images
+                    let program = program.unwrap_or_default();
+                    if program.entry.is_some() && options.load_addr.is_some() && options.entry.is_none() {
+                        println!("Argument ERROR: `{}` sets an absolute entry point, so `--load-addr` needs `--entry` as well.", path);
+                        process::exit(1);
+                    }
+
+                    let (boot, boot_registers) = match boot {
+                        Some((entry, registers)) => (Some(entry), registers),
+                        None => (None, vec![]),
+                    };
+                    let registers = boot_registers.iter().chain(program.registers.iter()).chain(options.init_regs.iter());
+                    for (register, value) in registers {
+                        processor.set_register(*register as usize, *value);
+                    }
+
+                    let entry = options.entry.or(boot).or(program.entry).unwrap_or(base);
+                    processor.set_register(1, entry);
+                },
+                (None, Some(path)) => {
+                    let snapshot = match Snapshot::load(Path::new(path)) {
+                        Ok(x) => x,
+                        Err(x) => {
+                            println!("Application ERROR: {}: {}", path, x);
+                            process::exit(3);
+                        },
+                    };
+
+                    // device registers are poked rather than saved to, since
+                    // saves would set off their side effects
+                    processor.restore(snapshot.state);
+                    memory.restore(0, &snapshot.memory[..]);
+                },
+                (None, None) => unreachable!(),
+            }
 
-            let memory = PrintMemory::from_data(mem);
-            let processor = MainProcessor::new();
+            let (sender, outcome) = channel();
+            processor.report_outcome(sender);
+
+            let (sender, snapshot) = channel();
+            if options.snapshot.is_some() {
+                processor.report_snapshot(sender);
+            }
+            processor.add_tracer(Box::new(PrintTracer::new(options.trace_level)));
+
+            if let Some(path) = &options.trace_file {
+                match File::create(path) {
+                    Ok(x) => processor.add_tracer(Box::new(JsonTracer::new(BufWriter::new(x)))),
+                    Err(x) => {
+                        println!("Application ERROR: {}", x);
+                        process::exit(3);
+                    },
+                }
+            }
+
+            let terminal = match input {
+                Input::Stdin if options.raw => raw_terminal(),
+                _ => None,
+            };
 
             println!("16BitRiscMachineSTART:=>");
 
@@ -71,7 +342,94 @@ fn main() {
 
             machine.run().unwrap().join_processors();
 
-            println!(":=>Machine Halted")
+            // the framebuffer only draws every `--fb-refresh` cycles, so it may be behind
+            if let Some(x) = &display {
+                let _ = x.refresh();
+            }
+
+            if let Some(x) = terminal {
+                restore_terminal(&x);
+            }
+
+            if let (Some(path), Ok(x)) = (&options.snapshot, snapshot.try_recv()) {
+                if let Err(x) = x.save(Path::new(path)) {
+                    println!("Application ERROR: {}: {}", path, x);
+                    process::exit(3);
+                }
+            }
+
+            match outcome.try_recv() {
+                Ok(Outcome::Faulted(x)) => {
+                    println!(":=>Machine FAULT: {}", x);
+                    process::exit(x.code() as i32);
+                },
+                _ => println!(":=>Machine Halted"),
+            }
+        },
+        "debug" => {
+            let (image, symbols) = match read_program(&args[2]) {
+                Ok(x) => x,
+                Err(x) => {
+                    println!("{}", x);
+                    process::exit(3);
+                },
+            };
+
+            // stdin belongs to the debugger's own prompt
+            let interrupts = Interrupts::new();
+            let mut memory = Bus::standard(Input::Bytes(vec![]), &interrupts, DEFAULT_SEED);
+            load(&mut memory, &args[2], &image.words, 0);
+
+            let mut processor = MainProcessor::new();
+            processor.connect_interrupts(interrupts);
+            start(&mut processor, &image);
+
+            let session = debugger::Session::new(processor, memory, symbols);
+            debugger::repl(session);
+        },
+        "gdbserver" => {
+            let options = match options::parse_gdbserver(&args[2..]) {
+                Ok(x) => x,
+                Err(x) => {
+                    println!("Argument ERROR: {}", x);
+                    process::exit(1);
+                },
+            };
+
+            let (image, symbols) = match read_program(&options.program) {
+                Ok(x) => x,
+                Err(x) => {
+                    println!("{}", x);
+                    process::exit(3);
+                },
+            };
+
+            let interrupts = Interrupts::new();
+            let mut memory = Bus::standard(Input::Stdin, &interrupts, DEFAULT_SEED);
+            load(&mut memory, &options.program, &image.words, 0);
+
+            let mut processor = MainProcessor::new();
+            processor.connect_interrupts(interrupts);
+            start(&mut processor, &image);
+
+            let session = debugger::Session::new(processor, memory, symbols);
+            if let Err(x) = gdbserver::serve(session, options.port) {
+                println!("Application ERROR: {}", x);
+                process::exit(3);
+            }
+        },
+        "disasm" => {
+            let image = match read_image(&args[2]) {
+                Ok(x) => x,
+                Err(x) => {
+                    println!("Application ERROR: {}", x);
+                    process::exit(3);
+                },
+            };
+
+            for line in disasm::disassemble(&image.words) {
+                println!("{}", line.render());
+            }
         },
         "scc" => {
             if args.len() < 4 {
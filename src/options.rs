@@ -0,0 +1,242 @@
+use RISC_16_bit::isa::get_reg;
+use RISC_16_bit::rng::DEFAULT_SEED;
+use RISC_16_bit::trace::TraceLevel;
+use std::collections::HashMap;
+use crate::expr::{evaluate, parse_number};
+
+/// Command line options for `run`.
+pub struct RunOptions {
+    /// `None` when the machine is resumed from a snapshot instead.
+    pub program: Option<String>,
+    pub trace_level: TraceLevel,
+    pub trace_file: Option<String>,
+    pub resume: Option<String>,
+    /// Where to save a snapshot once the machine halts or faults.
+    pub snapshot: Option<String>,
+    /// A machine description to build the memory bus from.
+    pub machine: Option<String>,
+    /// A file to read input from instead of stdin.
+    pub input_file: Option<String>,
+    /// A string, which may contain escapes, to read input from instead of stdin.
+    pub input_str: Option<String>,
+    /// Whether to put the terminal in raw mode while reading stdin.
+    pub raw: bool,
+    /// An image file to attach the disk controller to.
+    pub disk: Option<String>,
+    /// Whether to draw the framebuffer to the terminal.
+    pub framebuffer: bool,
+    /// A directory to dump framebuffer frames to instead of drawing them.
+    pub fb_dump: Option<String>,
+    /// How many cycles pass between frames.
+    pub fb_refresh: u32,
+    /// What the random number generator starts from.
+    pub seed: u16,
+    /// A ROM image for the machine to start in.
+    pub boot_rom: Option<String>,
+    /// Where the boot ROM is placed, and so where the machine starts.
+    pub boot_addr: Option<u16>,
+    /// Where the program is loaded, 0 unless given.
+    pub load_addr: Option<u16>,
+    /// Where to start running, ahead of anything the program's header says.
+    pub entry: Option<u16>,
+    /// Registers to set before starting, after the program's header sets its own.
+    pub init_regs: Vec<(u16, u16)>,
+    /// Further images to load, and where to load each.
+    pub images: Vec<(String, u16)>,
+}
+
+/// Command line options for `gdbserver`.
+pub struct GdbOptions {
+    pub program: String,
+    pub port: u16,
+}
+
+fn value<'a>(args: &mut impl Iterator<Item = &'a String>, flag: &str) -> Result<&'a String, String> {
+    match args.next() {
+        Some(x) => Ok(x),
+        None => Err(format!("`{}` expects a value.", flag)),
+    }
+}
+
+/// Reads an address or other word-sized number.
+fn word(text: &str) -> Option<u16> {
+    match parse_number(text) {
+        Some(x) if (0..=0xFFFF).contains(&x) => Some(x as u16),
+        _ => None,
+    }
+}
+
+/// Reads `register=value`, where the register is named as in the assembler and
+/// the value is a constant expression, which may be negative.
+fn init_reg(text: &str) -> Result<(u16, u16), String> {
+    let (register, value) = match text.split_once('=') {
+        Some(x) => x,
+        None => return Err(format!("`{}` is not of the form `register=value`.", text)),
+    };
+
+    let register = match get_reg(register) {
+        Some(x) => x,
+        None => return Err(format!("`{}` is not a register.", register)),
+    };
+    match evaluate(value, &HashMap::new()) {
+        Ok(x) if (-0x8000..=0xFFFF).contains(&x) => Ok((register, x as u16)),
+        _ => Err(format!("`{}` is not a valid register value.", value)),
+    }
+}
+
+/// Parses the arguments following `run`: the program, unless `--resume` is
+/// given, and then any options.
+pub fn parse_run(args: &[String]) -> Result<RunOptions, String> {
+    let mut args = args.iter().peekable();
+
+    let program = match args.peek() {
+        Some(x) if !x.starts_with("--") => args.next().map(|d| d.to_string()),
+        _ => None,
+    };
+
+    let mut options = RunOptions {
+        program,
+        trace_level: TraceLevel::Off,
+        trace_file: None,
+        resume: None,
+        snapshot: None,
+        machine: None,
+        input_file: None,
+        input_str: None,
+        raw: false,
+        disk: None,
+        framebuffer: false,
+        fb_dump: None,
+        fb_refresh: 10000,
+        seed: DEFAULT_SEED,
+        boot_rom: None,
+        boot_addr: None,
+        load_addr: None,
+        entry: None,
+        init_regs: vec![],
+        images: vec![],
+    };
+
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--trace-level" => {
+                options.trace_level = match value(&mut args, flag)?.as_str() {
+                    "off" => TraceLevel::Off,
+                    "ins" => TraceLevel::Instruction,
+                    "diff" => TraceLevel::RegisterDiff,
+                    x => return Err(format!("Unknown trace level `{}`, expected `off`, `ins` or `diff`.", x)),
+                };
+            },
+            "--trace" => options.trace_file = Some(value(&mut args, flag)?.to_string()),
+            "--resume" => options.resume = Some(value(&mut args, flag)?.to_string()),
+            "--snapshot-on-halt" => options.snapshot = Some(value(&mut args, flag)?.to_string()),
+            "--machine" => options.machine = Some(value(&mut args, flag)?.to_string()),
+            "--input" => options.input_file = Some(value(&mut args, flag)?.to_string()),
+            "--input-str" => options.input_str = Some(value(&mut args, flag)?.to_string()),
+            "--raw" => options.raw = true,
+            "--disk" => options.disk = Some(value(&mut args, flag)?.to_string()),
+            "--framebuffer" => options.framebuffer = true,
+            "--fb-dump" => options.fb_dump = Some(value(&mut args, flag)?.to_string()),
+            "--fb-refresh" => {
+                let cycles = value(&mut args, flag)?;
+                options.fb_refresh = match cycles.parse() {
+                    Ok(x) if x > 0 => x,
+                    _ => return Err(format!("`{}` is not a valid number of cycles.", cycles)),
+                };
+            },
+            "--seed" => {
+                let seed = value(&mut args, flag)?;
+                options.seed = match word(seed) {
+                    Some(x) => x,
+                    None => return Err(format!("`{}` is not a valid seed.", seed)),
+                };
+            },
+            "--boot-rom" => options.boot_rom = Some(value(&mut args, flag)?.to_string()),
+            "--boot-addr" => {
+                let address = value(&mut args, flag)?;
+                options.boot_addr = match word(address) {
+                    Some(x) => Some(x),
+                    None => return Err(format!("`{}` is not a valid address.", address)),
+                };
+            },
+            "--load-addr" | "--entry" => {
+                let address = value(&mut args, flag)?;
+                let address = match word(address) {
+                    Some(x) => Some(x),
+                    None => return Err(format!("`{}` is not a valid address.", address)),
+                };
+
+                if flag == "--entry" {
+                    options.entry = address;
+                } else {
+                    options.load_addr = address;
+                }
+            },
+            "--init-reg" => options.init_regs.push(init_reg(value(&mut args, flag)?)?),
+            "--image" => {
+                let image = value(&mut args, flag)?;
+                let (path, address) = match image.rsplit_once('@') {
+                    Some(x) => x,
+                    None => return Err(format!("`{}` is not of the form `file@address`.", image)),
+                };
+
+                match word(address) {
+                    Some(x) => options.images.push((path.to_string(), x)),
+                    None => return Err(format!("`{}` is not a valid address.", address)),
+                }
+            },
+            x => return Err(format!("Option `{}` not recognised.", x)),
+        }
+    }
+
+    if options.input_file.is_some() && options.input_str.is_some() {
+        return Err(String::from("Only one of `--input` and `--input-str` may be given."));
+    }
+    if options.boot_addr.is_some() && options.boot_rom.is_none() {
+        return Err(String::from("`--boot-addr` can only be given along with `--boot-rom`."));
+    }
+    if options.framebuffer && options.fb_dump.is_some() {
+        return Err(String::from("Only one of `--framebuffer` and `--fb-dump` may be given."));
+    }
+
+    let starts = options.load_addr.is_some() || options.entry.is_some() || !options.init_regs.is_empty() || !options.images.is_empty();
+    if starts && options.resume.is_some() {
+        return Err(String::from("`--load-addr`, `--entry`, `--init-reg` and `--image` cannot be given along with `--resume`."));
+    }
+
+    match (&options.program, &options.resume) {
+        (None, None) => Err(String::from("Not enough arguments supplied.")),
+        (Some(_), Some(_)) => Err(String::from("A program cannot be given along with `--resume`.")),
+        _ => Ok(options),
+    }
+}
+
+/// Parses the arguments following `gdbserver`, the first of which is the program.
+pub fn parse_gdbserver(args: &[String]) -> Result<GdbOptions, String> {
+    let mut args = args.iter();
+
+    let program = match args.next() {
+        Some(x) => x.to_string(),
+        None => return Err(String::from("Not enough arguments supplied.")),
+    };
+
+    let mut options = GdbOptions {
+        program,
+        port: 1234,
+    };
+
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--port" => {
+                let port = value(&mut args, flag)?;
+                options.port = match port.parse() {
+                    Ok(x) => x,
+                    Err(_) => return Err(format!("`{}` is not a valid port.", port)),
+                };
+            },
+            x => return Err(format!("Option `{}` not recognised.", x)),
+        }
+    }
+
+    Ok(options)
+}
@@ -0,0 +1,74 @@
+use modVM::*;
+use modVM::Query::*;
+use modVM::Response::*;
+use crate::bus::Inspect;
+
+/// Plain read/write memory, addressed from 0.
+pub struct Ram {
+    mem: Vec<u16>,
+}
+
+impl Ram {
+    /// Zeroed memory `len` words long.
+    pub fn new(len: usize) -> Ram {
+        Ram {
+            mem: vec![0; len],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.mem.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mem.is_empty()
+    }
+}
+
+impl Peripheral<u16> for Ram {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            model: String::from("RAM v.0.0.0"),
+        }
+    }
+
+    fn handle(&mut self, incoming: Query<u16>) -> Result<Response<u16>, u16> {
+        Ok(match incoming {
+            LoadRequest(x) => {
+                match self.mem.get(x as usize) {
+                    Some(y) => Data(*y),
+                    None => Fail(0),
+                }
+            },
+            SaveRequest(x, y) => {
+                match self.mem.get_mut(y as usize) {
+                    Some(z) => {
+                        *z = x;
+                        Good
+                    },
+                    None => Fail(0),
+                }
+            },
+        })
+    }
+}
+
+impl Inspect for Ram {
+    fn peek(&self, offset: u16) -> Option<u16> {
+        self.mem.get(offset as usize).cloned()
+    }
+
+    fn poke(&mut self, offset: u16, value: u16) -> bool {
+        match self.mem.get_mut(offset as usize) {
+            Some(x) => {
+                *x = value;
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn is_memory(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,135 @@
+use modVM::*;
+use modVM::Query::*;
+use modVM::Response::*;
+use crate::bus::Inspect;
+
+/// The seed `run` uses unless given `--seed`.
+pub const DEFAULT_SEED: u16 = 0x2545;
+
+/// What a seed of 0 is replaced with, since xorshift never leaves 0.
+const ZERO_SEED: u16 = 0xACE1;
+
+/// The next state after `x` of the 16-bit xorshift generator with shifts
+/// 7, 9 and 8, which visits every nonzero value once per 65535 steps.
+pub fn xorshift(mut x: u16) -> u16 {
+    x ^= x << 7;
+    x ^= x >> 9;
+    x ^= x << 8;
+    x
+}
+
+/// A pseudo-random number generator, stepping `xorshift` each time a number
+/// is read. The same seed always gives the same numbers.
+///
+/// | Offset | Word                                                        |
+/// |--------|-------------------------------------------------------------|
+/// | 0      | State. Saving to it seeds the generator; a seed of 0 is     |
+/// |        | replaced with 0xACE1.                                       |
+/// | 1      | Next number. Loading it steps the state and returns it.     |
+pub struct Rng {
+    state: u16,
+}
+
+impl Rng {
+    pub fn new(seed: u16) -> Rng {
+        let mut rng = Rng {
+            state: 0,
+        };
+        rng.seed(seed);
+        rng
+    }
+
+    pub fn seed(&mut self, seed: u16) {
+        self.state = if seed == 0 { ZERO_SEED } else { seed };
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Rng {
+        Rng::new(DEFAULT_SEED)
+    }
+}
+
+impl Peripheral<u16> for Rng {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            model: String::from("Xorshift RNG v.0.0.0"),
+        }
+    }
+
+    fn handle(&mut self, incoming: Query<u16>) -> Result<Response<u16>, u16> {
+        Ok(match incoming {
+            LoadRequest(0) => Data(self.state),
+            LoadRequest(1) => {
+                self.state = xorshift(self.state);
+                Data(self.state)
+            },
+            SaveRequest(x, 0) => {
+                self.seed(x);
+                Good
+            },
+            _ => Fail(0),
+        })
+    }
+}
+
+impl Inspect for Rng {
+    /// Offset 1 gives the number the next load will return, without stepping.
+    fn peek(&self, offset: u16) -> Option<u16> {
+        match offset {
+            0 => Some(self.state),
+            1 => Some(xorshift(self.state)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn next(rng: &mut Rng) -> u16 {
+        match rng.handle(LoadRequest(1)) {
+            Ok(Data(x)) => x,
+            _ => panic!("the generator failed to load"),
+        }
+    }
+
+    #[test]
+    fn default_seed_sequence() {
+        let mut rng = Rng::default();
+        let numbers: Vec<u16> = (0..4).map(|_| next(&mut rng)).collect();
+        assert_eq!(numbers, [0x0186, 0x25E7, 0xDA0C, 0xBE62]);
+        assert!(matches!(rng.handle(LoadRequest(0)), Ok(Data(0xBE62))));
+    }
+
+    #[test]
+    fn seeding_restarts_the_sequence() {
+        let mut rng = Rng::default();
+        next(&mut rng);
+        assert!(matches!(rng.handle(SaveRequest(DEFAULT_SEED, 0)), Ok(Good)));
+        assert_eq!(next(&mut rng), 0x0186);
+
+        // 0 would stick forever
+        assert!(matches!(rng.handle(SaveRequest(0, 0)), Ok(Good)));
+        assert_eq!(next(&mut rng), xorshift(0xACE1));
+        assert_eq!(xorshift(0xACE1), 0xD30F);
+    }
+
+    #[test]
+    fn visits_every_nonzero_value() {
+        let mut x = DEFAULT_SEED;
+        for step in 1..65535 {
+            x = xorshift(x);
+            assert!(x != DEFAULT_SEED && x != 0, "repeated after {} steps", step);
+        }
+        assert_eq!(xorshift(x), DEFAULT_SEED);
+    }
+
+    #[test]
+    fn other_offsets_fail() {
+        let mut rng = Rng::default();
+        assert!(matches!(rng.handle(LoadRequest(2)), Ok(Fail(_))));
+        assert!(matches!(rng.handle(SaveRequest(1, 1)), Ok(Fail(_))));
+    }
+}
@@ -0,0 +1,57 @@
+use modVM::*;
+use modVM::Query::*;
+use modVM::Response::*;
+use crate::bus::Inspect;
+
+/// The `Fail` code a ROM answers saves with, which the processor reports as
+/// `Fault::WriteProtected`.
+pub const WRITE_PROTECTED: u16 = 1;
+
+/// Read-only memory, addressed from 0, holding the words it was made with.
+/// Not even the host can poke it.
+pub struct Rom {
+    mem: Vec<u16>,
+}
+
+impl Rom {
+    pub fn new(contents: Vec<u16>) -> Rom {
+        Rom {
+            mem: contents,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.mem.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mem.is_empty()
+    }
+}
+
+impl Peripheral<u16> for Rom {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            model: String::from("ROM v.0.0.0"),
+        }
+    }
+
+    fn handle(&mut self, incoming: Query<u16>) -> Result<Response<u16>, u16> {
+        Ok(match incoming {
+            LoadRequest(x) => {
+                match self.mem.get(x as usize) {
+                    Some(y) => Data(*y),
+                    None => Fail(0),
+                }
+            },
+            SaveRequest(_, y) if (y as usize) < self.mem.len() => Fail(WRITE_PROTECTED),
+            SaveRequest(..) => Fail(0),
+        })
+    }
+}
+
+impl Inspect for Rom {
+    fn peek(&self, offset: u16) -> Option<u16> {
+        self.mem.get(offset as usize).cloned()
+    }
+}
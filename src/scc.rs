@@ -34,7 +34,7 @@ impl UncompiledFunction {
                             false,
                         ),
                         Calls(
-                            body.split(|d| d == '\n' || d == ' ')
+                            body.split(['\n', ' '])
                                 .map(|x| {
                                     x.to_string()
                                 })
@@ -55,11 +55,11 @@ impl UncompiledFunction {
                         false,
                     ),
                     Calls(
-                        body.split(|d| d == '\n' || d == ' ')
+                        body.split(['\n', ' '])
                             .map(|x| {
                                 x.to_string()
                             })
-                            .filter(|x| x.len() != 0)
+                            .filter(|x| !x.is_empty())
                             .collect::<Vec<String>>(),
                     ),
                 ))
@@ -107,9 +107,7 @@ impl ParsedFunc {
             let mut count = 0;
 
             for i in (self.1).0.iter() {
-                if let Ok(x) = i.parse::<u16>() {
-                    count += APPEND_INT;
-                } else if i.chars().next().unwrap() == '&' {
+                if i.parse::<u16>().is_ok() || i.starts_with('&') {
                     count += APPEND_INT;
                 } else {
                     count += CALL_LENGTH;
@@ -125,27 +123,26 @@ impl ParsedFunc {
     }
 
     pub fn compile(&self, symbols: &HashMap<String, usize>) -> Result<String, String> {
-        let mut body: String = if self.is_asm() {
+        let body: String = if self.is_asm() {
             (self.1).0
                 .join("\n")
         } else {
             (self.1).0.iter()
                 .map(|d| {
-                    if let Ok(_) = d.parse::<u16>() {
+                    if d.parse::<u16>().is_ok() {
                         format!("
 SET a 1
 ADD a e
 CPY out e
 SET a {}
 SAV a e", d)
-                    } else if d.chars().next().unwrap() == '&' {
-                        let index = d[1..].to_string();
+                    } else if let Some(index) = d.strip_prefix('&') {
                         format!("
 SET a 1
 ADD a e
 CPY out e
 SET a {}
-SAV a e", symbols.get(&index).unwrap())
+SAV a e", symbols.get(index).unwrap())
                     } else {
                         let index = d.to_string();
                         format!("
@@ -0,0 +1,176 @@
+use std::fs::{read, write};
+use std::path::Path;
+use crate::CpuState;
+
+/// Identifies a snapshot file.
+pub const MAGIC: [u8; 4] = *b"R16S";
+pub const VERSION: u16 = 2;
+
+const HEADER_LEN: usize = 6;
+
+/// Set in the flags when interrupts are enabled.
+const ENABLED: u16 = 1;
+/// Set in the flags when the next instruction runs before any interrupt.
+const HELD: u16 = 2;
+
+/// The number of words before memory starts in each version.
+fn cpu_len(version: u16) -> Option<usize> {
+    match version {
+        1 => Some(8),
+        2 => Some(10),
+        _ => None,
+    }
+}
+
+/// The state of a machine: the processor and every word of memory, as read by
+/// the processor. Words belonging to memory mapped devices are recorded too,
+/// and a resumed machine pokes them back into devices that can take them
+/// without side effects, such as the console mailbox. Other devices start
+/// afresh rather than having their registers saved to.
+///
+/// On disk this is `MAGIC`, the version, the registers, the saved pc, a flags
+/// word holding `ENABLED` and `HELD`, and then memory, all big-endian. Version
+/// 1 snapshots, which predate interrupts, have neither the saved pc nor the
+/// flags.
+pub struct Snapshot {
+    pub state: CpuState,
+    pub memory: Box<[u16; 65536]>,
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.state.interrupts_enabled {
+            flags |= ENABLED;
+        }
+        if self.state.interrupts_held {
+            flags |= HELD;
+        }
+        let cpu = [self.state.saved_pc, flags];
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + 2 * (10 + 65536));
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_be_bytes());
+
+        for word in self.state.registers.iter().chain(cpu.iter()).chain(self.memory.iter()) {
+            bytes.extend_from_slice(&word.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, String> {
+        if bytes.len() < HEADER_LEN || bytes[..4] != MAGIC {
+            return Err(String::from("not a snapshot"));
+        }
+
+        let version = u16::from_be_bytes([bytes[4], bytes[5]]);
+        let cpu = match cpu_len(version) {
+            Some(x) => x,
+            None => return Err(format!("snapshot version {} is not supported, expected at most {}", version, VERSION)),
+        };
+
+        let len = HEADER_LEN + 2 * (cpu + 65536);
+        if bytes.len() != len {
+            return Err(format!("snapshot is {} bytes long, expected {}", bytes.len(), len));
+        }
+
+        let mut words = bytes[HEADER_LEN..].chunks(2).map(|d| u16::from_be_bytes([d[0], d[1]]));
+
+        let mut state = CpuState::default();
+        for (register, word) in state.registers.iter_mut().zip(&mut words) {
+            *register = word;
+        }
+        if version >= 2 {
+            state.saved_pc = words.next().unwrap_or(0);
+            let flags = words.next().unwrap_or(0);
+            state.interrupts_enabled = flags & ENABLED != 0;
+            state.interrupts_held = flags & HELD != 0;
+        }
+
+        let mut memory = Box::new([0; 65536]);
+        for (cell, word) in memory.iter_mut().zip(words) {
+            *cell = word;
+        }
+
+        Ok(Snapshot {
+            state,
+            memory,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        write(path, self.to_bytes()).map_err(|x| x.to_string())
+    }
+
+    pub fn load(path: &Path) -> Result<Snapshot, String> {
+        match read(path) {
+            Ok(x) => Snapshot::from_bytes(&x),
+            Err(x) => Err(x.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        let mut memory = Box::new([0; 65536]);
+        memory[0] = 0x1234;
+        memory[8128] = 0xBEEF;
+        memory[65535] = 0xFFFF;
+
+        Snapshot {
+            state: CpuState {
+                registers: [1, 2, 3, 4, 5, 6, 7, 0x8000],
+                saved_pc: 42,
+                interrupts_enabled: true,
+                interrupts_held: true,
+            },
+            memory,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let original = snapshot();
+        let bytes = original.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + 2 * (10 + 65536));
+        assert_eq!(bytes[..4], MAGIC);
+
+        let read = Snapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(read.state, original.state);
+        assert_eq!(read.memory[..], original.memory[..]);
+    }
+
+    #[test]
+    fn version_1_has_no_interrupt_state() {
+        let original = snapshot();
+        let mut bytes = original.to_bytes();
+        bytes[4..6].copy_from_slice(&1u16.to_be_bytes());
+        // drop the saved pc and flags words
+        bytes.drain(HEADER_LEN + 16..HEADER_LEN + 20);
+
+        let read = Snapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(read.state.registers, original.state.registers);
+        assert_eq!(read.state.saved_pc, 0);
+        assert!(!read.state.interrupts_enabled && !read.state.interrupts_held);
+        assert_eq!(read.memory[..], original.memory[..]);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let bytes = snapshot().to_bytes();
+
+        assert!(Snapshot::from_bytes(b"R16").is_err());
+        assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert!(Snapshot::from_bytes(&magic).is_err());
+
+        let mut version = bytes;
+        version[4..6].copy_from_slice(&(VERSION + 1).to_be_bytes());
+        assert!(Snapshot::from_bytes(&version).is_err());
+    }
+}
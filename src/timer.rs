@@ -0,0 +1,233 @@
+use modVM::*;
+use modVM::Query::*;
+use modVM::Response::*;
+use crate::bus::Inspect;
+use crate::interrupt::InterruptLine;
+
+/// A free running 32-bit cycle counter and a countdown timer, counting calls
+/// to `Peripheral::cycle`. Under `run` the memory's thread cycles it
+/// continuously, so the count follows the host's clock rather than the
+/// program; the debugger cycles it once per instruction.
+///
+/// | Offset | Word                                                        |
+/// |--------|-------------------------------------------------------------|
+/// | 0      | Low half of the counter. Loading it latches the high half.  |
+/// | 1      | High half of the counter, as latched by the last load of 0. |
+/// | 2      | Reload. The countdown restarts from here when saved to and  |
+/// |        | each time it expires; 0 stops it.                           |
+/// | 3      | Status. Bit 0 is set when the countdown expires; save 1 to  |
+/// |        | clear it.                                                   |
+///
+/// Saving to either half of the counter replaces that half. When connected to
+/// an interrupt line, the timer also raises it each time the countdown expires.
+pub struct Timer {
+    count: u32,
+    latched: u16,
+    reload: u16,
+    remaining: u16,
+    status: u16,
+    line: Option<InterruptLine>,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            count: 0,
+            latched: 0,
+            reload: 0,
+            remaining: 0,
+            status: 0,
+            line: None,
+        }
+    }
+
+    pub fn connect(&mut self, line: InterruptLine) {
+        self.line = Some(line);
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Timer {
+        Timer::new()
+    }
+}
+
+impl Peripheral<u16> for Timer {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            model: String::from("Timer v.0.0.0"),
+        }
+    }
+
+    fn handle(&mut self, incoming: Query<u16>) -> Result<Response<u16>, u16> {
+        Ok(match incoming {
+            LoadRequest(0) => {
+                self.latched = (self.count >> 16) as u16;
+                Data(self.count as u16)
+            },
+            LoadRequest(x) => match self.peek(x) {
+                Some(y) => Data(y),
+                None => Fail(0),
+            },
+            SaveRequest(x, 0) => {
+                self.count = (self.count & 0xFFFF_0000) | x as u32;
+                Good
+            },
+            SaveRequest(x, 1) => {
+                self.count = (self.count & 0xFFFF) | (x as u32) << 16;
+                Good
+            },
+            SaveRequest(x, 2) => {
+                self.reload = x;
+                self.remaining = x;
+                Good
+            },
+            SaveRequest(x, 3) => {
+                self.status &= !x;
+                Good
+            },
+            _ => Fail(0),
+        })
+    }
+
+    fn cycle(&mut self) -> Result<(), u16> {
+        self.count = self.count.wrapping_add(1);
+
+        if self.reload != 0 {
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                self.status |= 1;
+                self.remaining = self.reload;
+
+                if let Some(x) = &self.line {
+                    x.raise();
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Inspect for Timer {
+    /// Offset 0 gives the low half of the counter without latching the high half.
+    fn peek(&self, offset: u16) -> Option<u16> {
+        match offset {
+            0 => Some(self.count as u16),
+            1 => Some(self.latched),
+            2 => Some(self.reload),
+            3 => Some(self.status),
+            _ => None,
+        }
+    }
+
+    /// Offset 1 replaces the high half of the counter as well as the latched
+    /// copy, so that a snapshot's counter comes back whole. The status can be
+    /// set without raising the interrupt.
+    fn poke(&mut self, offset: u16, value: u16) -> bool {
+        match offset {
+            0 => self.count = (self.count & 0xFFFF_0000) | value as u32,
+            1 => {
+                self.count = (self.count & 0xFFFF) | (value as u32) << 16;
+                self.latched = value;
+            },
+            2 => {
+                self.reload = value;
+                self.remaining = value;
+            },
+            3 => self.status = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(timer: &mut Timer, offset: u16) -> u16 {
+        match timer.handle(LoadRequest(offset)) {
+            Ok(Data(x)) => x,
+            _ => panic!("offset {} cannot be loaded", offset),
+        }
+    }
+
+    fn write(timer: &mut Timer, offset: u16, value: u16) {
+        assert!(matches!(timer.handle(SaveRequest(value, offset)), Ok(Good)));
+    }
+
+    fn cycles(timer: &mut Timer, count: u32) {
+        for _ in 0..count {
+            timer.cycle().unwrap();
+        }
+    }
+
+    #[test]
+    fn counter_latches_the_high_half() {
+        let mut timer = Timer::new();
+        write(&mut timer, 0, 0xFFFE);
+        cycles(&mut timer, 3);
+
+        assert_eq!(read(&mut timer, 1), 0);
+        assert_eq!(read(&mut timer, 0), 1);
+        assert_eq!(read(&mut timer, 1), 1);
+
+        write(&mut timer, 1, 0xABCD);
+        cycles(&mut timer, 1);
+        assert_eq!(read(&mut timer, 1), 1);
+        assert_eq!(read(&mut timer, 0), 2);
+        assert_eq!(read(&mut timer, 1), 0xABCD);
+    }
+
+    #[test]
+    fn countdown_expires_and_reloads() {
+        let mut timer = Timer::new();
+        cycles(&mut timer, 10);
+        assert_eq!(read(&mut timer, 3), 0);
+
+        write(&mut timer, 2, 3);
+        cycles(&mut timer, 2);
+        assert_eq!(read(&mut timer, 3), 0);
+        cycles(&mut timer, 1);
+        assert_eq!(read(&mut timer, 3), 1);
+
+        write(&mut timer, 3, 1);
+        assert_eq!(read(&mut timer, 3), 0);
+        cycles(&mut timer, 3);
+        assert_eq!(read(&mut timer, 3), 1);
+        assert_eq!(read(&mut timer, 2), 3);
+    }
+
+    #[test]
+    fn zero_reload_stops_the_countdown() {
+        let mut timer = Timer::new();
+        write(&mut timer, 2, 1);
+        write(&mut timer, 2, 0);
+        cycles(&mut timer, 5);
+        assert_eq!(read(&mut timer, 3), 0);
+        assert!(matches!(timer.handle(LoadRequest(4)), Ok(Fail(_))));
+    }
+
+    #[test]
+    fn pokes_restore_the_registers() {
+        let mut timer = Timer::new();
+        for (offset, value) in [0x0002, 0x0001, 3, 1].iter().enumerate() {
+            assert!(timer.poke(offset as u16, *value));
+        }
+        assert!(!timer.poke(4, 0));
+
+        assert_eq!(read(&mut timer, 1), 1);
+        assert_eq!(read(&mut timer, 0), 2);
+        assert_eq!(read(&mut timer, 3), 1);
+        cycles(&mut timer, 3);
+        assert_eq!(read(&mut timer, 0), 5);
+
+        // the countdown carries on from the poked reload
+        write(&mut timer, 3, 1);
+        cycles(&mut timer, 2);
+        assert_eq!(read(&mut timer, 3), 0);
+        cycles(&mut timer, 1);
+        assert_eq!(read(&mut timer, 3), 1);
+    }
+}
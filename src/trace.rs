@@ -0,0 +1,135 @@
+use std::io::{stderr, Write};
+use std::fmt::Write as FmtWrite;
+use crate::isa::{reg_name, Instruction};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TraceLevel {
+    Off,
+    Instruction,
+    RegisterDiff,
+}
+
+/// One instruction that ran to completion, with the register file on either
+/// side of it.
+pub struct Retired {
+    pub pc: u16,
+    pub instruction: Instruction,
+    pub before: [u16; 8],
+    pub after: [u16; 8],
+    /// Every memory write as (address, value), in order.
+    pub writes: Vec<(u16, u16)>,
+}
+
+impl Retired {
+    /// The registers this instruction changed, leaving out `count` when it
+    /// only stepped over the instruction.
+    pub fn changed(&self) -> Vec<usize> {
+        let next = self.pc.wrapping_add(self.instruction.opcode.size());
+
+        (0..8)
+            .filter(|d| {
+                self.before[*d] != self.after[*d] && !(*d == 1 && self.after[1] == next)
+            })
+            .collect()
+    }
+}
+
+pub trait Tracer: Send {
+    /// How much the tracer wants to see. Tracers at `TraceLevel::Off` are
+    /// never called.
+    fn level(&self) -> TraceLevel;
+
+    fn retire(&mut self, event: &Retired);
+
+    /// Called once the processor stops.
+    fn flush(&mut self) {}
+}
+
+/// Writes each retired instruction to stderr, so that it stays apart from
+/// anything the program prints.
+pub struct PrintTracer {
+    level: TraceLevel,
+}
+
+impl PrintTracer {
+    pub fn new(level: TraceLevel) -> PrintTracer {
+        PrintTracer {
+            level,
+        }
+    }
+}
+
+impl Tracer for PrintTracer {
+    fn level(&self) -> TraceLevel {
+        self.level
+    }
+
+    fn retire(&mut self, event: &Retired) {
+        let mut line = format!("{:04X}: {}", event.pc, event.instruction);
+
+        if self.level >= TraceLevel::RegisterDiff {
+            line = format!("{:<24}", line);
+
+            for r in event.changed() {
+                line.push_str(&format!(" {}: {} -> {}", reg_name(r as u16).unwrap(), event.before[r], event.after[r]));
+            }
+        }
+
+        let _ = writeln!(stderr(), "{}", line.trim_end());
+    }
+}
+
+/// Writes one JSON object per retired instruction (JSON Lines), for diffing and
+/// analysing runs with external tools:
+///
+/// `{"pc":15,"op":"SAV","operands":[2,4],"text":"SAV a c","before":[...],"after":[...],"writes":[{"address":8081,"value":72}]}`
+pub struct JsonTracer<W: Write + Send> {
+    out: W,
+}
+
+impl<W: Write + Send> JsonTracer<W> {
+    pub fn new(out: W) -> JsonTracer<W> {
+        JsonTracer {
+            out,
+        }
+    }
+}
+
+fn json_array(values: &[u16]) -> String {
+    let values: Vec<String> = values.iter()
+        .map(|d| {
+            d.to_string()
+        })
+        .collect();
+
+    format!("[{}]", values.join(","))
+}
+
+impl<W: Write + Send> Tracer for JsonTracer<W> {
+    fn level(&self) -> TraceLevel {
+        TraceLevel::RegisterDiff
+    }
+
+    fn retire(&mut self, event: &Retired) {
+        let operands = &event.instruction.operands[..event.instruction.opcode.operands().len()];
+
+        let mut writes = String::new();
+        for (i, (address, value)) in event.writes.iter().enumerate() {
+            let _ = write!(writes, "{}{{\"address\":{},\"value\":{}}}", if i == 0 { "" } else { "," }, address, value);
+        }
+
+        let _ = writeln!(self.out, "{{\"pc\":{},\"op\":\"{}\",\"operands\":{},\"text\":\"{}\",\"before\":{},\"after\":{},\"writes\":[{}]}}",
+            event.pc,
+            event.instruction.opcode.mnemonic(),
+            json_array(operands),
+            event.instruction,
+            json_array(&event.before),
+            json_array(&event.after),
+            writes,
+        );
+    }
+
+    fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+}
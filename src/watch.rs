@@ -0,0 +1,191 @@
+use modVM::*;
+use modVM::Query::*;
+use modVM::Response::*;
+use crate::bus::Inspect;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    /// A save that changes the stored value.
+    Write,
+    Read,
+    /// Any load or save.
+    Access,
+}
+
+/// Watches the addresses `start..=end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+/// A watched load or save. For loads `old` and `new` are both the value read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub watchpoint: Watchpoint,
+    pub address: u16,
+    pub write: bool,
+    pub old: u16,
+    pub new: u16,
+}
+
+/// Wraps a peripheral, passing every query through to it and noting the
+/// loads and saves that hit a watchpoint.
+pub struct Watched<P: Inspect> {
+    inner: P,
+    pub watchpoints: Vec<Watchpoint>,
+    hits: Vec<WatchHit>,
+}
+
+impl<P: Inspect> Watched<P> {
+    pub fn new(inner: P) -> Watched<P> {
+        Watched {
+            inner,
+            watchpoints: vec![],
+            hits: vec![],
+        }
+    }
+
+    /// The wrapped peripheral, for queries that should not trigger watchpoints.
+    pub fn inner(&mut self) -> &mut P {
+        &mut self.inner
+    }
+
+    /// Returns and forgets the hits recorded so far.
+    pub fn take_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.hits)
+    }
+
+    /// The first watchpoint a load (or, if `write`, a save) of `address` hits.
+    pub fn watching(&self, address: u16, write: bool) -> Option<Watchpoint> {
+        self.watchpoints.iter()
+            .find(|d| {
+                d.start <= address && address <= d.end && (d.kind == WatchKind::Access || (d.kind == WatchKind::Write) == write)
+            })
+            .cloned()
+    }
+}
+
+impl<P: Inspect> Peripheral<u16> for Watched<P> {
+    fn metadata(&self) -> Metadata {
+        self.inner.metadata()
+    }
+
+    fn handle(&mut self, incoming: Query<u16>) -> Result<Response<u16>, u16> {
+        match incoming {
+            LoadRequest(x) => {
+                let response = self.inner.handle(LoadRequest(x))?;
+
+                if let (Some(watchpoint), Data(y)) = (self.watching(x, false), &response) {
+                    self.hits.push(WatchHit { watchpoint, address: x, write: false, old: *y, new: *y });
+                }
+
+                Ok(response)
+            },
+            SaveRequest(x, y) => {
+                let watchpoint = match self.watching(y, true) {
+                    Some(w) => w,
+                    None => return self.inner.handle(SaveRequest(x, y)),
+                };
+
+                let old = self.inner.peek(y).unwrap_or(0);
+                let response = self.inner.handle(SaveRequest(x, y))?;
+
+                if watchpoint.kind == WatchKind::Access || old != x {
+                    self.hits.push(WatchHit { watchpoint, address: y, write: true, old, new: x });
+                }
+
+                Ok(response)
+            },
+        }
+    }
+
+    fn cycle(&mut self) -> Result<(), u16> {
+        self.inner.cycle()
+    }
+
+    fn boot(&mut self) -> Result<(), u16> {
+        self.inner.boot()
+    }
+
+    fn halt(&mut self) -> Result<(), u16> {
+        self.inner.halt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Memory(Vec<u16>);
+
+    impl Peripheral<u16> for Memory {
+        fn metadata(&self) -> Metadata {
+            Metadata {
+                model: String::from("Test Memory"),
+            }
+        }
+
+        fn handle(&mut self, incoming: Query<u16>) -> Result<Response<u16>, u16> {
+            Ok(match incoming {
+                LoadRequest(x) => Data(self.0[x as usize]),
+                SaveRequest(x, y) => {
+                    self.0[y as usize] = x;
+                    Good
+                },
+            })
+        }
+    }
+
+    impl Inspect for Memory {
+        fn peek(&self, offset: u16) -> Option<u16> {
+            self.0.get(offset as usize).cloned()
+        }
+    }
+
+    fn watched(kind: WatchKind) -> Watched<Memory> {
+        let mut memory = Watched::new(Memory(vec![0; 16]));
+        memory.watchpoints.push(Watchpoint { start: 4, end: 7, kind });
+        memory
+    }
+
+    #[test]
+    fn writes_that_change_memory() {
+        let mut memory = watched(WatchKind::Write);
+        memory.handle(SaveRequest(9, 3)).unwrap();
+        memory.handle(SaveRequest(9, 5)).unwrap();
+        memory.handle(SaveRequest(9, 5)).unwrap();
+        memory.handle(LoadRequest(5)).unwrap();
+
+        let hits = memory.take_hits();
+        assert_eq!(hits.len(), 1);
+        assert_eq!((hits[0].address, hits[0].write, hits[0].old, hits[0].new), (5, true, 0, 9));
+        assert!(memory.take_hits().is_empty());
+    }
+
+    #[test]
+    fn reads() {
+        let mut memory = watched(WatchKind::Read);
+        memory.handle(SaveRequest(2, 7)).unwrap();
+        match memory.handle(LoadRequest(7)) {
+            Ok(Data(x)) => assert_eq!(x, 2),
+            _ => panic!("the load failed"),
+        }
+        memory.handle(LoadRequest(8)).unwrap();
+
+        let hits = memory.take_hits();
+        assert_eq!(hits.len(), 1);
+        assert_eq!((hits[0].address, hits[0].write, hits[0].old, hits[0].new), (7, false, 2, 2));
+    }
+
+    #[test]
+    fn accesses() {
+        let mut memory = watched(WatchKind::Access);
+        memory.handle(SaveRequest(0, 4)).unwrap();
+        memory.handle(LoadRequest(4)).unwrap();
+        memory.inner().handle(LoadRequest(4)).unwrap();
+
+        assert_eq!(memory.take_hits().iter().map(|x| x.write).collect::<Vec<_>>(), [true, false]);
+    }
+}